@@ -0,0 +1,135 @@
+//! Persistent settings from /etc/go-modules.toml
+//!
+//! Only the small subset of toml this tool needs is understood: `key = value` lines where the value is a string,
+//! an array of strings or a boolean, `#` starts a comment. A missing file means all defaults.
+//! ``` text
+//! # labels printed on the enclosure, in logical slot order
+//! slot_labels = ["A", "B", "C", "D"]
+//! # extra firmware filename layouts, tried after the default 20-10-1-5-0-0-9.srec
+//! firmware_patterns = ["{0}_{1}_{2}_{3}-v{4}.{5}.{6}.srec"]
+//! # slots running qualified firmware that update must not touch
+//! pinned_slots = ["2"]
+//! # never stop and restart nodered and go-simulink, for installations that don't run them
+//! manage_services = false
+//! ```
+
+use std::fs;
+
+pub const CONFIG_FILE: &str = "/etc/go-modules.toml";
+
+#[derive(Default)]
+pub struct Config {
+    /// labels to show instead of the logical slot numbers, in logical slot order
+    pub slot_labels: Option<Vec<String>>,
+    /// firmware filename patterns to recognize besides the default naming
+    pub firmware_patterns: Vec<String>,
+    /// slots excluded from updates, by label or number
+    pub pinned_slots: Vec<String>,
+    /// whether nodered and go-simulink are stopped while flashing, None if the file doesn't say
+    pub manage_services: Option<bool>,
+}
+
+enum Value {
+    String(String),
+    Array(Vec<String>),
+    Bool(bool),
+}
+
+impl Config {
+    /// load the config file, a missing file results in the default config
+    pub fn load() -> Result<Self, String> {
+        match fs::read_to_string(CONFIG_FILE) {
+            Ok(contents) => Self::parse(&contents),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut config = Self::default();
+        for (i, line) in contents.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{} line {}: expected key = value", CONFIG_FILE, i + 1))?;
+            let key = key.trim();
+            let value = parse_value(value.trim())
+                .map_err(|err| format!("{} line {}: {}", CONFIG_FILE, i + 1, err))?;
+            match (key, value) {
+                ("slot_labels", Value::Array(labels)) => config.slot_labels = Some(labels),
+                ("firmware_patterns", Value::Array(patterns)) => {
+                    config.firmware_patterns = patterns
+                }
+                ("pinned_slots", Value::Array(slots)) => config.pinned_slots = slots,
+                ("manage_services", Value::Bool(manage)) => config.manage_services = Some(manage),
+                ("manage_services", _) => {
+                    return Err(format!(
+                        "{} line {}: manage_services must be true or false",
+                        CONFIG_FILE,
+                        i + 1
+                    ))
+                }
+                (key @ ("slot_labels" | "firmware_patterns" | "pinned_slots"), _) => {
+                    return Err(format!(
+                        "{} line {}: {} must be an array of strings",
+                        CONFIG_FILE,
+                        i + 1,
+                        key
+                    ))
+                }
+                _ => {
+                    return Err(format!(
+                        "{} line {}: unknown key {}",
+                        CONFIG_FILE,
+                        i + 1,
+                        key
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// remove a # comment from a line, ignoring # inside strings
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Result<Value, String> {
+    if let Some(items) = raw.strip_prefix('[').and_then(|raw| raw.strip_suffix(']')) {
+        let mut array = Vec::new();
+        for item in items
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+        {
+            match parse_value(item)? {
+                Value::String(item) => array.push(item),
+                _ => {
+                    return Err(format!(
+                        "only arrays of strings are supported, got {}",
+                        item
+                    ))
+                }
+            }
+        }
+        Ok(Value::Array(array))
+    } else if let Some(string) = raw.strip_prefix('"').and_then(|raw| raw.strip_suffix('"')) {
+        Ok(Value::String(string.to_string()))
+    } else if let Ok(boolean) = raw.parse::<bool>() {
+        Ok(Value::Bool(boolean))
+    } else {
+        Err(format!("invalid value {}", raw))
+    }
+}
@@ -0,0 +1,109 @@
+//! Firmware from a provisioning server instead of the local firmware folder
+//!
+//! With the `download` feature `overwrite <slot> <url>` flashes the firmware file at the url and
+//! `--firmware-url-base <url>` takes the firmware to choose from from a server instead of the local firmware folder,
+//! the server lists its firmware files one filename per line in `<url>/index`. Only plain http is supported, the http
+//! client is built without tls so https urls are refused at startup. A file is downloaded completely before its module
+//! is wiped, so a download that is too slow or larger than any module firmware fails without touching the module.
+//! Without the feature urls are refused at startup.
+//! ``` text
+//! $ curl http://provisioning/firmware/index
+//! 20-10-1-5-0-0-9.srec
+//! 20-20-2-5-0-1-2.srec
+//! ```
+
+#[cfg(feature = "download")]
+use std::{io::Read, time::Duration};
+
+/// how long a single download may take
+#[cfg(feature = "download")]
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// the largest file that is accepted, far above the srec of a module firmware
+#[cfg(feature = "download")]
+const MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+/// whether this build can download firmware
+pub const SUPPORTED: bool = cfg!(feature = "download");
+
+/// whether a firmware argument is a url instead of a file in the firmware folder
+pub fn is_url(arg: &str) -> bool {
+    arg.starts_with("http://") || arg.starts_with("https://")
+}
+
+/// whether a url can be downloaded, the http client has no tls so only http urls can
+pub fn check_url(url: &str) -> Result<(), String> {
+    if url.starts_with("https://") {
+        Err(format!("{} uses https, only plain http is supported", url))
+    } else if !url.starts_with("http://") {
+        Err(format!("{} is not an http url", url))
+    } else {
+        Ok(())
+    }
+}
+
+/// the filename at the end of a url, without its query or fragment
+pub fn basename(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// the firmware filenames the server at base lists in its index
+#[cfg(feature = "download")]
+pub fn list(base: &str) -> Result<Vec<String>, String> {
+    let index = String::from_utf8(get(&format!("{}/index", base.trim_end_matches('/')))?)
+        .map_err(|_| format!("{}/index is not a text file", base))?;
+    Ok(index
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// download the file at url, all of it is in memory before anything is flashed
+#[cfg(feature = "download")]
+pub fn fetch(url: &str) -> Result<String, String> {
+    String::from_utf8(get(url)?).map_err(|_| format!("{} is not a firmware file", url))
+}
+
+#[cfg(feature = "download")]
+fn get(url: &str) -> Result<Vec<u8>, String> {
+    let response = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .get(url)
+        .call()
+        .map_err(|err| format!("could not download {}: {}", url, err))?;
+    let mut contents = Vec::new();
+    //one byte more than allowed, to tell a file of exactly MAX_SIZE from a larger one
+    response
+        .into_reader()
+        .take(MAX_SIZE + 1)
+        .read_to_end(&mut contents)
+        .map_err(|err| format!("could not download {}: {}", url, err))?;
+    if contents.len() as u64 > MAX_SIZE {
+        return Err(format!(
+            "{} is larger than {} bytes, that is no module firmware",
+            url, MAX_SIZE
+        ));
+    }
+    Ok(contents)
+}
+
+/// never reached, urls are refused when SUPPORTED is false
+#[cfg(not(feature = "download"))]
+pub fn list(base: &str) -> Result<Vec<String>, String> {
+    Err(format!(
+        "can't list {}, built without the download feature",
+        base
+    ))
+}
+
+/// never reached, urls are refused when SUPPORTED is false
+#[cfg(not(feature = "download"))]
+pub fn fetch(url: &str) -> Result<String, String> {
+    Err(format!(
+        "can't download {}, built without the download feature",
+        url
+    ))
+}
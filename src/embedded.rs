@@ -0,0 +1,22 @@
+//! Firmware baked into the binary for single purpose provisioning images
+//!
+//! Built with the `embedded` feature the srec file at the absolute path in `GO_MODULES_EMBEDDED_FIRMWARE` is included
+//! in the binary, `--embedded` then flashes it instead of looking in /lib/firmware/gocontroll. The file has to be
+//! named like any other firmware file so its version can be recognized.
+//! ``` text
+//! GO_MODULES_EMBEDDED_FIRMWARE=/path/to/20-10-1-5-0-0-9.srec cargo build --features embedded
+//! ```
+
+/// the path the firmware was embedded from and its contents
+#[cfg(feature = "embedded")]
+pub const FIRMWARE: Option<(&str, &str)> = Some((
+    env!(
+        "GO_MODULES_EMBEDDED_FIRMWARE",
+        "set GO_MODULES_EMBEDDED_FIRMWARE to the absolute path of the srec file to embed"
+    ),
+    include_str!(env!("GO_MODULES_EMBEDDED_FIRMWARE")),
+));
+
+/// the path the firmware was embedded from and its contents
+#[cfg(not(feature = "embedded"))]
+pub const FIRMWARE: Option<(&str, &str)> = None;
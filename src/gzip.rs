@@ -0,0 +1,197 @@
+//! Appending to gzip compressed files for --history
+//!
+//! A gzip file may hold several members one after another, `zcat` and `gunzip` decompress them as a single stream.
+//! Appending a snapshot is therefore writing one more complete member to the end of the file, the members written
+//! before are never read or rewritten. The members are compressed with fixed huffman deflate blocks and a small lz77
+//! search, which is plenty for the repetitive json snapshots and avoids pulling in a compression crate.
+
+use std::{fs::OpenOptions, io, io::Write, path::Path};
+
+/// how far back lz77 looks for a match, the largest distance deflate can encode
+const WINDOW: usize = 32 * 1024;
+/// the longest match deflate can encode
+const MAX_MATCH: usize = 258;
+/// how many earlier positions with the same hash are tried for every match
+const MAX_CHAIN: usize = 32;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// append data as one more gzip member to the file at path, creating the file if it doesn't exist
+pub fn append(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?
+        .write_all(&member(data))
+}
+
+/// a complete gzip member holding data
+fn member(data: &[u8]) -> Vec<u8> {
+    //no file name, no modification time, unix
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+    out.extend(deflate(data));
+    out.extend(crc32(data).to_le_bytes());
+    out.extend((data.len() as u32).to_le_bytes());
+    out
+}
+
+/// writes bits least significant first, the order deflate packs them into bytes
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= value << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// huffman codes are defined most significant bit first, so they are reversed before writing
+    fn write_code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    /// a literal, length or end of block symbol in the fixed huffman code
+    fn write_symbol(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// the raw deflate stream of data, a single final block with the fixed huffman code
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter {
+        out: Vec::with_capacity(data.len() / 2),
+        buffer: 0,
+        count: 0,
+    };
+    //final block, fixed huffman
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    //the last position every 3 byte hash was seen at and the position before that with the same hash
+    let mut head = vec![usize::MAX; 1 << 15];
+    let mut previous = vec![usize::MAX; data.len()];
+
+    let mut position = 0;
+    while position < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if position + 2 < data.len() {
+            let mut candidate = head[hash(data, position)];
+            let mut chain = 0;
+            while candidate != usize::MAX && position - candidate <= WINDOW && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[position..])
+                    .take(MAX_MATCH)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    (best_length, best_distance) = (length, position - candidate);
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+        if best_length >= 3 {
+            write_match(&mut writer, best_length, best_distance);
+            for skipped in position..position + best_length {
+                insert(data, skipped, &mut head, &mut previous);
+            }
+            position += best_length;
+        } else {
+            writer.write_symbol(data[position] as u16);
+            insert(data, position, &mut head, &mut previous);
+            position += 1;
+        }
+    }
+    writer.write_symbol(256);
+    writer.finish()
+}
+
+fn hash(data: &[u8], position: usize) -> usize {
+    ((data[position] as usize) << 10
+        ^ (data[position + 1] as usize) << 5
+        ^ data[position + 2] as usize)
+        & 0x7fff
+}
+
+/// remember position as the latest one with its hash, if there are 3 bytes left to hash
+fn insert(data: &[u8], position: usize, head: &mut [usize], previous: &mut [usize]) {
+    if position + 2 < data.len() {
+        let hash = hash(data, position);
+        previous[position] = head[hash];
+        head[hash] = position;
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap();
+    writer.write_symbol(257 + code as u16);
+    writer.write(
+        (length - LENGTH_BASE[code] as usize) as u32,
+        LENGTH_EXTRA[code] as u32,
+    );
+    let code = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap();
+    //the fixed distance code is the code number in 5 bits
+    writer.write_code(code as u32, 5);
+    writer.write(
+        (distance - DISTANCE_BASE[code] as usize) as u32,
+        DISTANCE_EXTRA[code] as u32,
+    );
+}
+
+/// the crc32 gzip uses, reflected with polynomial 0xEDB88320
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -1,23 +1,43 @@
+mod config;
+mod download;
+mod embedded;
+mod gzip;
+mod permit;
+mod remote;
+mod report;
+mod sha256;
+mod transport;
+mod watchdog;
+
 use std::{
+    collections::BTreeMap,
     env,
     fmt::{Display, Write},
-    fs::{self, File},
+    fs,
+    future::Future,
+    io::{self, IsTerminal},
     mem,
     process::{exit, Command},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use futures::StreamExt;
-
-use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+use inquire::{Confirm, MultiSelect, Select};
 
-use inquire::Select;
+use console::style;
 
-use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressState, ProgressStyle};
 
 use tokio::{task, task::JoinSet, time, time::timeout};
 
-use gpio_cdev::{AsyncLineEventHandle, Chip, EventRequestFlags, LineRequestFlags};
+use nix::sys::statvfs::statvfs;
+
+use config::Config;
+use permit::WipePermit;
+use transport::{Interrupt, Spi};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -28,6 +48,46 @@ const BOOTMESSAGE_LENGTH_CHECK: usize = 61;
 
 const SLOT_PROMPT: &str = "Which slot to overwrite?";
 
+const MODULES_DIR: &str = "/usr/lib/gocontroll/";
+const FIRMWARE_DIR: &str = "/lib/firmware/gocontroll/";
+/// known good images --recover-on-corruption flashes when an upload leaves a module corrupted, named like firmware files
+const RECOVERY_DIR: &str = "/lib/firmware/gocontroll/recovery/";
+/// name of the modules file in MODULES_DIR
+const DEFAULT_MODULES_FILE_NAME: &str = "modules";
+/// environment variable to set the modules file name when --modules-file isn't given
+const MODULES_FILE_ENV: &str = "GO_MODULES_MODULES_FILE";
+const STATS_FILE: &str = "/usr/lib/gocontroll/module-stats";
+/// the successful flashes of every module recorded with --count-flashes, one qr:count line per module
+const FLASH_COUNTS_FILE: &str = "/usr/lib/gocontroll/module-flash-counts";
+/// the slots update all finished so far, one per line, for --resume to skip after an interruption
+const PROGRESS_FILE: &str = "/usr/lib/gocontroll/module-update-progress";
+
+/// sysfs file switching the module reset line, {slot} is replaced by the logical slot number
+const DEFAULT_RESET_PATH: &str = "/sys/class/leds/ResetM-{slot}/brightness";
+/// environment variable to set the reset path template when --reset-path isn't given
+const RESET_PATH_ENV: &str = "GO_MODULES_RESET_PATH";
+/// the newest bootloader protocol version whose frames this tool knows, modules report theirs in their info response \
+/// the bootloaders so far have no version field and report 0, the legacy protocol
+const BOOT_PROTOCOL: u8 = 0;
+/// how many times the wipe command is sent before an upload gives up with the firmware untouched
+const WIPE_ATTEMPTS: u32 = 3;
+/// how long the scan of all slots may take before the slots that haven't answered are skipped
+const SCAN_TIMEOUT: Duration = Duration::from_secs(5);
+/// spi clock speed used on every controller when --spi-speed isn't given, a faster default for a controller needs
+/// benchmark results from the slot farthest from its processor first
+const DEFAULT_SPI_SPEED: u32 = 2_000_000;
+/// spi clock speeds tried by the benchmark command, in increasing order
+const BENCHMARK_SPEEDS: [u32; 8] = [
+    1_000_000, 2_000_000, 4_000_000, 6_000_000, 8_000_000, 10_000_000, 12_000_000, 16_000_000,
+];
+/// amount of info requests sent at every speed by the benchmark command
+const BENCHMARK_TRIALS: u32 = 100;
+/// default minimum free space in bytes on the filesystem of the modules file before flashing
+const DEFAULT_MIN_FREE_SPACE: u64 = 64 * 1024;
+/// the manufacturer ids modules report and who they belong to \
+/// empty until the ids are taken from the manufacturer registry, an unknown id is shown as just its number
+const MANUFACTURERS: &[(u32, &str)] = &[];
+
 const USAGE: &str = "Usage:
 go-modules <command> [subcommands]
 or
@@ -37,13 +97,567 @@ commands:
 scan							Scan the modules in the controller
 update <all/slot#>				In case of all, try to update all modules, in case of a slot number, try to update that slot specifically
 overwrite <slot> <firmware>		Overwrite the firmware in <slot> with <firmware>
+overwrite --qr <qr> <firmware>	Overwrite the firmware of the module with front or back QR code <qr> with <firmware>
+overwrite --from-csv <file>		Overwrite the firmware of every slot listed in a slot,firmware csv file at once
+overwrite --type <type>			Overwrite every module of <type> like 2chOut or 6chIn at once with the firmware of the software version given with --to
+stats							Print the upload retry statistics per slot recorded with --stats
+flash-count <qr>				Print how many times the module with front QR code <qr> was flashed, recorded with --count-flashes
+sync							Rewrite /usr/lib/gocontroll/modules from the modules in the controller and show what changed
+benchmark <slot>				Find the highest spi speed at which the module in <slot> answers without errors
+diff <modules file>				Compare the module types and firmware in the controller with the modules file of another controller
+firmware-info <file>			Print the module type and software version of a firmware file and the modules in /usr/lib/gocontroll/modules it matches
+reset all						Pulse the reset line of every slot at once, restarting the modules
+config							Print the settings in effect after merging the defaults, /etc/go-modules.toml, the environment and the options, with where each came from
+slots can be given by their number or by their label set with slot_labels in /etc/go-modules.toml
+firmware files named differently than 20-10-1-5-0-0-9.srec are recognized with firmware_patterns in /etc/go-modules.toml
 
 examples:
 go-modules										Use with the tui (recommended)
 go-modules scan									Scan all modules in the controller
+go-modules scan --table							Scan all modules and print them as a table
+go-modules scan --oneline						Scan all modules and print the state of every slot on one line
+go-modules scan --no-save --json				Scan all modules and print them as json without touching /usr/lib/gocontroll/modules
 go-modules update all							Try to update all modules in the controller
 go-modules update 1								Try to update the module in slot 1
-go-modules overwrite 1 20-10-1-5-0-0-9.srec		Forcefully overwrite the module in slot 1 with 20-10-1-5-0-0-9.srec (can be used to downgrade modules)";
+go-modules reset all							Restart all modules in the controller
+go-modules update all --resume					Update all modules, skipping the slots an interrupted update all already finished
+go-modules update all --older-than 1.2.0		Only update the modules with software older than 1.2.0
+go-modules overwrite 1 20-10-1-5-0-0-9.srec		Forcefully overwrite the module in slot 1 with 20-10-1-5-0-0-9.srec (can be used to downgrade modules)
+go-modules overwrite 1 http://server/20-10-1-5-0-0-9.srec	Download 20-10-1-5-0-0-9.srec from server and overwrite the module in slot 1 with it, needs the download feature
+go-modules overwrite --qr 1234 20-10-1-5-0-0-9.srec	Forcefully overwrite the module with QR code 1234 with 20-10-1-5-0-0-9.srec
+go-modules overwrite --from-csv bench.csv		Flash the firmware listed per slot in bench.csv, after checking every row
+go-modules overwrite --type 2chOut --to 5.0.9	Flash every 2 channel output module with its firmware of software version 5.0.9
+go-modules --remote ctrl-1 --remote ctrl-2 update all	Update all modules in the controllers ctrl-1 and ctrl-2 over ssh
+
+options:
+--capabilities					Print the version and the supported commands and options as json, then exit
+--qr <qr>						Select the module by its front or back QR code, see overwrite
+--qr-side <front|back|both>		Match --qr against and let scan show only the front or back QR code, default both
+--version-style <dotted|dashed>	Show software versions as 1.2.3 or as 1-2-3 like the firmware filenames, default dotted
+--from-csv <file>				Take the slots and firmware to overwrite from a slot,firmware csv file, see overwrite
+--type <type>					Select the modules to overwrite by their type, see overwrite
+--to <version>					The software version to overwrite the modules of --type with, like 1.2.3
+--stats							Record the retries and wipe time of every upload in /usr/lib/gocontroll/module-stats
+--count-flashes					Count the successful flashes of every module by front QR code in /usr/lib/gocontroll/module-flash-counts
+--strict						Turn pre-flight warnings into errors
+--min-free-space <bytes>		Warn (or error with --strict) before flashing if the modules file can't be saved, default 65536
+--remote <host>					Run the command on <host> over ssh instead of locally, can be given multiple times
+--agent							Report progress in the remote protocol, used by --remote
+--safe							Keep the old firmware until the new one is verified, not supported by the module bootloader yet
+--no-color						Don't color the output, also disabled by setting NO_COLOR or when not writing to a terminal
+--delta							Only upload the changed regions of the firmware, not supported by the module bootloader yet
+--older-than <version>			Only update modules with software older than <version> like 1.2.3, newer ones are skipped
+--auto-recover					Restore modules left without firmware by an interrupted upload to their last known firmware without asking, before an update
+--recover-on-corruption			Flash the newest recovery image for the hardware from /lib/firmware/gocontroll/recovery when a flash leaves a module corrupted
+--reset-path <template>			Module reset control path, {slot} is replaced by the slot number, also read from GO_MODULES_RESET_PATH, default /sys/class/leds/ResetM-{slot}/brightness
+--modules-file <name>			Name of the modules file in /usr/lib/gocontroll, for a file per test station, also read from GO_MODULES_MODULES_FILE, default modules
+--table							Print the modules found by scan as a table
+--oneline						Print the state of every slot on one line like IV: [1:6chIn/5.0.9] [2:empty], see scan
+--expect-modules <n>			Let scan fail unless exactly <n> modules are found
+--fail-if-empty					Let scan fail when no modules are found
+--no-save						Let scan only print the modules it found, without rewriting /usr/lib/gocontroll/modules
+--verbose						Log every failed firmware line and whether the module refused it or didn't respond at all, let scan show the bootloader protocol, print the srec header of every firmware file before it is flashed
+--trace-spi						Log every spi frame sent and received as hex with its message type and line number on stderr
+--keep-services					Leave nodered and go-simulink running, unless a process has a module spi bus open when starting
+--manage-services				Stop and restart nodered and go-simulink, even when manage_services = false in /etc/go-modules.toml
+--no-manage-services			Never stop or restart nodered and go-simulink, also set with manage_services = false in /etc/go-modules.toml
+--attempts <n>					Let update all try up to <n> times, scanning and retrying only the slots that failed, default 1
+--resume						Let update all skip the slots the previous, interrupted update all finished, see /usr/lib/gocontroll/module-update-progress
+--auto-retry-corrupted <n>		Wipe and flash a module left with corrupted firmware again up to <n> times before failing, default 0
+--json							Print the modules found by scan as json, and errors and warnings as json objects on stderr
+--history <path>				Append the modules found by every full scan with a timestamp to the gzip compressed <path>, read it with zcat
+--report-url <url>				POST the modules file as json to <url> after a successful run, needs the report feature
+--embedded						Use the firmware built into the binary instead of /lib/firmware/gocontroll, needs the embedded feature
+--firmware-url-base <url>		Use the firmware listed in <url>/index on a plain http server instead of /lib/firmware/gocontroll, needs the download feature
+--pin <slots>					Never update these comma separated slots, also set with pinned_slots in /etc/go-modules.toml
+--force							Overwrite pinned slots anyway, or overwrite <slot> with firmware made for other hardware
+--watchdog-path <dev>			Feed the watchdog <dev> like /dev/watchdog while the services are stopped, so the controller doesn't reset mid-flash
+--watchdog-interval <ms>		Feed the watchdog of --watchdog-path every <ms> milliseconds, default 1000
+--post-flash <cmd>				Run <cmd> with sh after every successful flash, with GO_MODULES_SLOT, GO_MODULES_OLD_VERSION, GO_MODULES_NEW_VERSION, GO_MODULES_QR_FRONT and GO_MODULES_QR_BACK set
+--post-flash-required			Fail the run when a --post-flash command exits with an error
+--spi-speed <hz>				Talk to the modules at <hz> instead of the default of 2000000
+--inter-module-delay <ms>		Wait <ms> milliseconds between flashing one module and the next where modules are flashed one by one, default 0
+--list-slots					Print the spidev, interrupt line and reset control of every slot without probing the modules, then exit
+--print-mapping-json			Print the slot mapping of --list-slots as json, then exit
+--controller-info				Print the detected controller, its slot count, the device tree hardware string and the firmware folder, then exit";
+
+/// commands supported by this version, reported by --capabilities
+const COMMANDS: &[&str] = &[
+    "scan",
+    "update",
+    "overwrite",
+    "stats",
+    "flash-count",
+    "sync",
+    "benchmark",
+    "firmware-info",
+    "diff",
+    "reset",
+    "config",
+];
+
+/// options supported by this version, reported by --capabilities \
+/// --safe and --delta are accepted but left out, the module bootloader can't keep the old firmware or take a partial
+/// upload yet so they do nothing
+const OPTIONS: &[&str] = &[
+    "--capabilities",
+    "--qr",
+    "--qr-side",
+    "--version-style",
+    "--from-csv",
+    "--type",
+    "--to",
+    "--stats",
+    "--count-flashes",
+    "--strict",
+    "--min-free-space",
+    "--remote",
+    "--agent",
+    "--no-color",
+    "--older-than",
+    "--auto-recover",
+    "--recover-on-corruption",
+    "--reset-path",
+    "--modules-file",
+    "--table",
+    "--oneline",
+    "--expect-modules",
+    "--fail-if-empty",
+    "--no-save",
+    "--verbose",
+    "--trace-spi",
+    "--keep-services",
+    "--manage-services",
+    "--no-manage-services",
+    "--attempts",
+    "--resume",
+    "--auto-retry-corrupted",
+    "--json",
+    "--history",
+    #[cfg(feature = "report")]
+    "--report-url",
+    #[cfg(feature = "embedded")]
+    "--embedded",
+    #[cfg(feature = "download")]
+    "--firmware-url-base",
+    "--pin",
+    "--force",
+    "--list-slots",
+    "--print-mapping-json",
+    "--controller-info",
+    "--spi-speed",
+    "--inter-module-delay",
+    "--watchdog-path",
+    "--watchdog-interval",
+    "--post-flash",
+    "--post-flash-required",
+];
+
+/// labels of the slots as printed on the enclosure, in logical slot order, set once the controller is known
+static SLOT_LABELS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// where to report the modules file to after a successful run, set once at startup
+static REPORT_URL: OnceLock<String> = OnceLock::new();
+
+/// how software versions are shown, set once at startup
+static VERSION_STYLE: OnceLock<VersionStyle> = OnceLock::new();
+
+/// name of the modules file in MODULES_DIR, set once at startup
+static MODULES_FILE_NAME: OnceLock<String> = OnceLock::new();
+
+/// template of the module reset control path, set once at startup
+static RESET_PATH: OnceLock<String> = OnceLock::new();
+
+/// the spi clock speed used to talk to the modules, set once at startup from --spi-speed or the controller default
+static SPI_SPEED: OnceLock<u32> = OnceLock::new();
+
+/// the modules flashed during this run and the file they were flashed with as json, for the report
+static FLASHED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// print errors and warnings as json objects, set once at startup by --json
+static JSON_MESSAGES: AtomicBool = AtomicBool::new(false);
+
+/// the wall time of the whole update all batch, for the report
+static FLASH_TIME: OnceLock<Duration> = OnceLock::new();
+
+/// the modules flashed completely during this run, saved by the sigint handler when the run is stopped halfway
+static COMPLETED: Mutex<Vec<ModuleRecord>> = Mutex::new(Vec::new());
+
+/// the slots that didn't answer the scan within SCAN_TIMEOUT, their columns in the modules file are left as they were
+static MISSED_SLOTS: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// the slots whose info response was one byte repeated, with that byte, a sign of a MISO line stuck low or high
+static STUCK_SLOTS: Mutex<BTreeMap<u8, u8>> = Mutex::new(BTreeMap::new());
+
+/// set when a --post-flash command failed with --post-flash-required, which turns a successful run into a failed one
+static POST_FLASH_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// held while the modules file is rewritten, so two saves never interleave
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+/// held while the flash counts file is rewritten, update all finishes modules from several tasks at once
+static FLASH_COUNTS_LOCK: Mutex<()> = Mutex::new(());
+
+/// command line options, anything starting with -- is taken out of the arguments and stored here
+#[derive(Clone)]
+struct Options {
+    /// print the supported commands and options as json and exit
+    capabilities: bool,
+    /// select the module to overwrite by its front or back QR code instead of its slot
+    qr: Option<u32>,
+    /// the QR code --qr is matched against and scan shows
+    qr_side: QrSide,
+    /// how software versions are shown
+    version_style: VersionStyle,
+    /// overwrite the slots listed in this slot,firmware csv file
+    from_csv: Option<String>,
+    /// overwrite every module of this type
+    module_type: Option<String>,
+    /// the software version to overwrite the modules of --type with
+    to: Option<SoftwareVersion>,
+    /// append the amount of retries of every upload to the stats file
+    stats: bool,
+    /// count the successful flashes of every module in the flash counts file
+    count_flashes: bool,
+    /// turn pre-flight warnings into errors
+    strict: bool,
+    /// minimum free space in bytes required to save the modules file after flashing
+    min_free_space: u64,
+    /// run the command on these controllers over ssh instead of locally
+    remote: Vec<String>,
+    /// report progress with the remote protocol instead of progress bars, used by --remote
+    agent: bool,
+    /// keep the old firmware until the new one is verified, not supported by the module bootloader so only warns
+    safe: bool,
+    /// don't color the output
+    no_color: bool,
+    /// only upload changed regions, not supported by the module bootloader so only warns
+    delta: bool,
+    /// only update modules with software below this version
+    older_than: Option<SoftwareVersion>,
+    /// restore wiped modules to their last known firmware without asking
+    auto_recover: bool,
+    /// flash the recovery image from RECOVERY_DIR when a flash leaves a module corrupted
+    recover_on_corruption: bool,
+    /// template of the module reset control path instead of DEFAULT_RESET_PATH
+    reset_path: Option<String>,
+    /// name of the modules file in MODULES_DIR instead of DEFAULT_MODULES_FILE_NAME
+    modules_file: Option<String>,
+    /// print the scanned modules as a table
+    table: bool,
+    /// print the state of every slot on a single line
+    oneline: bool,
+    /// the number of modules scan has to find
+    expect_modules: Option<usize>,
+    /// fail the scan when no modules are found
+    fail_if_empty: bool,
+    /// scan without saving the modules found to the modules file
+    no_save: bool,
+    /// log every failed firmware line and why it failed
+    verbose: bool,
+    /// log every spi frame as hex
+    trace_spi: bool,
+    /// leave nodered and go-simulink running if they aren't using the module spi bus
+    keep_services: bool,
+    /// whether to stop and restart the services at all, overrides manage_services in the config, None to use it
+    manage_services: Option<bool>,
+    /// how many times update all tries the slots that failed
+    attempts: u32,
+    /// skip the slots that the interrupted previous update all finished
+    resume: bool,
+    /// how many times a flash that corrupted the firmware is repeated right away
+    auto_retry_corrupted: u32,
+    /// print the modules found by scan as json
+    json: bool,
+    /// append every full scan as json to this gzip file
+    history: Option<String>,
+    /// POST the modules file as json to this url after a successful run
+    report_url: Option<String>,
+    /// flash the firmware built into the binary instead of the ones in the firmware folder
+    embedded: bool,
+    /// take the firmware from the server at this url instead of the firmware folder
+    firmware_url_base: Option<String>,
+    /// slots that update never touches, by label or number, from --pin and pinned_slots in the config
+    pin: Vec<String>,
+    /// allow deliberate changes that are refused otherwise, like overwriting a pinned slot
+    force: bool,
+    /// print the spidev, interrupt line and reset control of every slot and exit
+    list_slots: bool,
+    /// print the slot mapping as json and exit
+    print_mapping_json: bool,
+    /// print what controller the tool detected and exit
+    controller_info: bool,
+    /// spi clock speed instead of DEFAULT_SPI_SPEED
+    spi_speed: Option<u32>,
+    /// pause between flashing modules one after another, so the backplane power can recover from the inrush
+    inter_module_delay: Duration,
+    /// the watchdog device to feed while the services are stopped
+    watchdog_path: Option<String>,
+    /// how often the watchdog is fed
+    watchdog_interval: Duration,
+    /// shell command run after every successful flash
+    post_flash: Option<String>,
+    /// fail the run when the post flash command fails
+    post_flash_required: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            capabilities: false,
+            qr: None,
+            qr_side: QrSide::Both,
+            version_style: VersionStyle::Dotted,
+            from_csv: None,
+            module_type: None,
+            to: None,
+            stats: false,
+            count_flashes: false,
+            strict: false,
+            min_free_space: DEFAULT_MIN_FREE_SPACE,
+            remote: Vec::new(),
+            agent: false,
+            safe: false,
+            no_color: false,
+            delta: false,
+            older_than: None,
+            auto_recover: false,
+            recover_on_corruption: false,
+            reset_path: None,
+            modules_file: None,
+            table: false,
+            oneline: false,
+            expect_modules: None,
+            fail_if_empty: false,
+            no_save: false,
+            verbose: false,
+            trace_spi: false,
+            keep_services: false,
+            manage_services: None,
+            attempts: 1,
+            resume: false,
+            auto_retry_corrupted: 0,
+            json: false,
+            history: None,
+            report_url: None,
+            embedded: false,
+            firmware_url_base: None,
+            pin: Vec::new(),
+            force: false,
+            list_slots: false,
+            print_mapping_json: false,
+            controller_info: false,
+            spi_speed: None,
+            inter_module_delay: Duration::ZERO,
+            watchdog_path: None,
+            watchdog_interval: Duration::from_secs(1),
+            post_flash: None,
+            post_flash_required: false,
+        }
+    }
+}
+
+impl Options {
+    /// whether the slot is excluded from updates
+    fn is_pinned(&self, slot: u8) -> bool {
+        self.pin.iter().any(|pin| parse_slot(pin) == Some(slot))
+    }
+
+    /// split the command line arguments into options and positional arguments, the positional arguments still start with the program name
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<(Self, Vec<String>), String> {
+        let mut options = Self::default();
+        let mut positional = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--capabilities" => options.capabilities = true,
+                "--stats" => options.stats = true,
+                "--count-flashes" => options.count_flashes = true,
+                "--strict" => options.strict = true,
+                "--remote" => options
+                    .remote
+                    .push(args.next().ok_or("--remote requires a host")?),
+                "--agent" => options.agent = true,
+                "--safe" => options.safe = true,
+                "--no-color" => options.no_color = true,
+                "--delta" => options.delta = true,
+                "--auto-recover" => options.auto_recover = true,
+                "--recover-on-corruption" => options.recover_on_corruption = true,
+                "--table" => options.table = true,
+                "--oneline" => options.oneline = true,
+                "--verbose" => options.verbose = true,
+                "--trace-spi" => options.trace_spi = true,
+                "--fail-if-empty" => options.fail_if_empty = true,
+                "--no-save" => options.no_save = true,
+                "--expect-modules" => {
+                    let count = args
+                        .next()
+                        .ok_or("--expect-modules requires a number of modules")?;
+                    options.expect_modules = Some(
+                        count
+                            .parse::<usize>()
+                            .map_err(|_| format!("Invalid number of modules entered {}", count))?,
+                    );
+                }
+                "--keep-services" => options.keep_services = true,
+                "--manage-services" => options.manage_services = Some(true),
+                "--no-manage-services" => options.manage_services = Some(false),
+                "--json" => options.json = true,
+                "--embedded" => options.embedded = true,
+                "--force" => options.force = true,
+                "--list-slots" => options.list_slots = true,
+                "--print-mapping-json" => options.print_mapping_json = true,
+                "--controller-info" => options.controller_info = true,
+                "--pin" => options.pin.extend(
+                    args.next()
+                        .ok_or("--pin requires a comma separated list of slots")?
+                        .split(',')
+                        .map(str::to_string),
+                ),
+                "--history" => {
+                    options.history = Some(args.next().ok_or("--history requires a path")?)
+                }
+                "--firmware-url-base" => {
+                    options.firmware_url_base =
+                        Some(args.next().ok_or("--firmware-url-base requires a url")?)
+                }
+                "--from-csv" => {
+                    options.from_csv = Some(args.next().ok_or("--from-csv requires a csv file")?)
+                }
+                "--type" => {
+                    options.module_type = Some(
+                        args.next()
+                            .ok_or("--type requires a module type like 2chOut")?,
+                    )
+                }
+                "--to" => {
+                    let version = args
+                        .next()
+                        .ok_or("--to requires a software version like 1.2.3")?;
+                    options.to =
+                        Some(SoftwareVersion::parse(&version).ok_or_else(|| {
+                            format!("Invalid software version entered {}", version)
+                        })?);
+                }
+                "--report-url" => {
+                    options.report_url = Some(args.next().ok_or("--report-url requires a url")?)
+                }
+                "--reset-path" => {
+                    options.reset_path =
+                        Some(args.next().ok_or("--reset-path requires a path template")?)
+                }
+                "--modules-file" => {
+                    options.modules_file =
+                        Some(args.next().ok_or("--modules-file requires a file name")?)
+                }
+                "--min-free-space" => {
+                    let bytes = args
+                        .next()
+                        .ok_or("--min-free-space requires a size in bytes")?;
+                    options.min_free_space = bytes
+                        .parse::<u64>()
+                        .map_err(|_| format!("Invalid size entered {}", bytes))?;
+                }
+                "--older-than" => {
+                    let version = args
+                        .next()
+                        .ok_or("--older-than requires a software version like 1.2.3")?;
+                    options.older_than =
+                        Some(SoftwareVersion::parse(&version).ok_or_else(|| {
+                            format!("Invalid software version entered {}", version)
+                        })?);
+                }
+                "--resume" => options.resume = true,
+                "--attempts" => {
+                    let attempts = args.next().ok_or("--attempts requires a number")?;
+                    options.attempts = attempts
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|attempts| *attempts > 0)
+                        .ok_or_else(|| {
+                            format!("Invalid number of attempts entered {}", attempts)
+                        })?;
+                }
+                "--auto-retry-corrupted" => {
+                    let retries = args
+                        .next()
+                        .ok_or("--auto-retry-corrupted requires a number")?;
+                    options.auto_retry_corrupted = retries
+                        .parse::<u32>()
+                        .map_err(|_| format!("Invalid number of retries entered {}", retries))?;
+                }
+                "--spi-speed" => {
+                    let speed = args.next().ok_or("--spi-speed requires a speed in Hz")?;
+                    options.spi_speed = Some(
+                        speed
+                            .parse::<u32>()
+                            .ok()
+                            .filter(|speed| *speed > 0)
+                            .ok_or(format!("Invalid spi speed entered {}", speed))?,
+                    );
+                }
+                "--inter-module-delay" => {
+                    let delay = args
+                        .next()
+                        .ok_or("--inter-module-delay requires a time in milliseconds")?;
+                    options.inter_module_delay = Duration::from_millis(
+                        delay
+                            .parse::<u64>()
+                            .map_err(|_| format!("Invalid delay entered {}", delay))?,
+                    );
+                }
+                "--watchdog-path" => {
+                    options.watchdog_path =
+                        Some(args.next().ok_or("--watchdog-path requires a device")?)
+                }
+                "--watchdog-interval" => {
+                    let interval = args
+                        .next()
+                        .ok_or("--watchdog-interval requires a time in milliseconds")?;
+                    options.watchdog_interval = Duration::from_millis(
+                        interval
+                            .parse::<u64>()
+                            .ok()
+                            .filter(|interval| *interval > 0)
+                            .ok_or_else(|| format!("Invalid interval entered {}", interval))?,
+                    );
+                }
+                "--qr" => {
+                    let qr = args.next().ok_or("--qr requires a QR code")?;
+                    options.qr = Some(
+                        qr.parse::<u32>()
+                            .map_err(|_| format!("Invalid QR code entered {}", qr))?,
+                    );
+                }
+                "--post-flash" => {
+                    options.post_flash = Some(args.next().ok_or("--post-flash requires a command")?)
+                }
+                "--post-flash-required" => options.post_flash_required = true,
+                "--qr-side" => {
+                    let side = args
+                        .next()
+                        .ok_or("--qr-side requires front, back or both")?;
+                    options.qr_side = QrSide::parse(&side).ok_or_else(|| {
+                        format!("Invalid QR side entered {}, use front, back or both", side)
+                    })?;
+                }
+                "--version-style" => {
+                    let style = args
+                        .next()
+                        .ok_or("--version-style requires dotted or dashed")?;
+                    options.version_style = VersionStyle::parse(&style).ok_or_else(|| {
+                        format!(
+                            "Invalid version style entered {}, use dotted or dashed",
+                            style
+                        )
+                    })?;
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => positional.push(arg),
+            }
+        }
+        Ok((options, positional))
+    }
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 struct FirmwareVersion {
@@ -54,17 +668,9 @@ impl FirmwareVersion {
     /// create a FirmwareVersion from a filename for example 20-10-1-5-0-0-9.srec
     fn from_filename(name: String) -> Option<Self> {
         let mut firmware: [u8; 7] = [0u8; 7];
-        if let Some(no_extension) = name.split('.').next() {
-            let numbers = no_extension.split('-');
-
-            for (i, num) in numbers.enumerate() {
-                let part = firmware.get_mut(i)?;
-                if let Ok(file_part) = num.parse::<u8>() {
-                    *part = file_part;
-                } else {
-                    return None;
-                }
-            }
+        let no_extension = name.split('.').next()?;
+        for (i, number) in no_extension.split('-').enumerate() {
+            *firmware.get_mut(i)? = number.parse::<u8>().ok()?;
         }
         Some(Self { firmware })
     }
@@ -74,11 +680,72 @@ impl FirmwareVersion {
         self.firmware.get(4..7).unwrap()
     }
 
+    /// get the software part of the firmware version as a comparable version
+    fn get_software_version(&self) -> SoftwareVersion {
+        SoftwareVersion {
+            major: self.firmware[4],
+            minor: self.firmware[5],
+            patch: self.firmware[6],
+        }
+    }
+
     /// get the hardware part of the firmware version
     fn get_hardware(&self) -> &[u8] {
         self.firmware.get(0..4).unwrap()
     }
 
+    /// the kind of module, decoded from the hardware part of the firmware version
+    fn module_type(&self) -> Option<&'static str> {
+        let hardware = self.get_hardware();
+        match (hardware[1], hardware[2]) {
+            (10, 1) => Some("6 Channel Input"),
+            (10, 2) => Some("10 Channel Input"),
+            (10, 3) => Some("4-20mA Input"),
+            (20, 1) => Some("2 Channel Output"),
+            (20, 2) => Some("6 Channel Output"),
+            (20, 3) => Some("10 Channel Output"),
+            (30, 3) => Some("ANLEG IR"),
+            (40, 1) => Some("ANLEG RTC Control"),
+            _ => None,
+        }
+    }
+
+    /// a short name of the kind of module for compact output like 6chIn
+    fn module_type_short(&self) -> Option<&'static str> {
+        let hardware = self.get_hardware();
+        match (hardware[1], hardware[2]) {
+            (10, 1) => Some("6chIn"),
+            (10, 2) => Some("10chIn"),
+            (10, 3) => Some("4-20mAIn"),
+            (20, 1) => Some("2chOut"),
+            (20, 2) => Some("6chOut"),
+            (20, 3) => Some("10chOut"),
+            (30, 3) => Some("IR"),
+            (40, 1) => Some("RTC"),
+            _ => None,
+        }
+    }
+
+    /// whether the kind of module is the one named, by its short or full name ignoring case, spaces and dashes
+    fn is_module_type(&self, name: &str) -> bool {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(char::is_ascii_alphanumeric)
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+        let name = normalize(name);
+        [self.module_type_short(), self.module_type()]
+            .into_iter()
+            .flatten()
+            .any(|known| normalize(known) == name)
+    }
+
+    /// a module straight from the factory reports a valid header but an all zero version, so its hardware is unknown too
+    fn is_factory_blank(&self) -> bool {
+        self.firmware == [0; 7]
+    }
+
     /// get a string version of the firmware version like 20-10-1-5-0-0-9
     fn as_string(&self) -> String {
         format!(
@@ -105,10 +772,295 @@ impl Display for FirmwareVersion {
     }
 }
 
+/// a part of a firmware filename pattern
+#[derive(Debug, Clone)]
+enum PatternPart {
+    /// text that has to appear literally in the filename
+    Literal(String),
+    /// a decimal number that goes into this byte of the firmware version
+    Field(usize),
+}
+
+/// a firmware filename layout other than the default 20-10-1-5-0-0-9.srec, for firmware from other build systems \
+/// `{0}` to `{3}` are the hardware bytes and `{4}` to `{6}` the software bytes, everything else is literal text,
+/// for example `{0}_{1}_{2}_{3}-v{4}.{5}.{6}.srec` matches 20_10_1_5-v0.0.9.srec
+#[derive(Debug, Clone)]
+struct FilenamePattern {
+    parts: Vec<PatternPart>,
+}
+
+impl FilenamePattern {
+    /// parse a pattern, every field has to appear exactly once and two fields need some text between them
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut seen = [false; 7];
+        let mut rest = pattern;
+        while !rest.is_empty() {
+            if let Some(field) = rest.strip_prefix('{') {
+                let (index, after) = field
+                    .split_once('}')
+                    .ok_or_else(|| format!("unclosed {{ in {}", pattern))?;
+                let index = index
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|index| *index < 7)
+                    .ok_or_else(|| {
+                        format!(
+                            "invalid field {{{}}} in {}, expected {{0}} to {{6}}",
+                            index, pattern
+                        )
+                    })?;
+                if seen[index] {
+                    return Err(format!("field {{{}}} appears twice in {}", index, pattern));
+                }
+                if let Some(PatternPart::Field(_)) = parts.last() {
+                    return Err(format!(
+                        "fields need a separator between them in {}",
+                        pattern
+                    ));
+                }
+                seen[index] = true;
+                parts.push(PatternPart::Field(index));
+                rest = after;
+            } else {
+                let end = rest.find('{').unwrap_or(rest.len());
+                parts.push(PatternPart::Literal(rest[..end].to_string()));
+                rest = &rest[end..];
+            }
+        }
+        if let Some(missing) = seen.iter().position(|seen| !seen) {
+            return Err(format!("field {{{}}} is missing in {}", missing, pattern));
+        }
+        Ok(Self { parts })
+    }
+
+    /// get the firmware version out of a filename that follows this pattern
+    fn matches(&self, name: &str) -> Option<FirmwareVersion> {
+        let mut firmware = [0u8; 7];
+        let mut rest = name;
+        for part in &self.parts {
+            match part {
+                PatternPart::Literal(text) => rest = rest.strip_prefix(text.as_str())?,
+                PatternPart::Field(index) => {
+                    let end = rest
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(rest.len());
+                    firmware[*index] = rest[..end].parse::<u8>().ok()?;
+                    rest = &rest[end..];
+                }
+            }
+        }
+        rest.is_empty().then_some(FirmwareVersion { firmware })
+    }
+}
+
+/// where the contents of a firmware file come from
+#[derive(Debug, Clone)]
+enum FirmwareSource {
+    /// a file in /lib/firmware/gocontroll
+    Directory,
+    /// built into the binary with the embedded feature
+    Embedded(&'static str),
+    /// downloaded from this url right before flashing, with the download feature
+    Remote(String),
+    /// a recovery image in /lib/firmware/gocontroll/recovery
+    Recovery,
+}
+
+/// a firmware file in /lib/firmware/gocontroll, its name doesn't have to be the default one made by as_filename
+#[derive(Debug, Clone)]
+struct FirmwareFile {
+    version: FirmwareVersion,
+    filename: String,
+    source: FirmwareSource,
+}
+
+impl FirmwareFile {
+    /// recognize a firmware file by the default naming or any of the given patterns
+    fn from_filename(filename: String, patterns: &[FilenamePattern]) -> Option<Self> {
+        let version = FirmwareVersion::from_filename(filename.clone()).or_else(|| {
+            patterns
+                .iter()
+                .find_map(|pattern| pattern.matches(&filename))
+        })?;
+        Some(Self {
+            version,
+            filename,
+            source: FirmwareSource::Directory,
+        })
+    }
+
+    /// the firmware embedded in the binary, recognized by the name of the file it was embedded from
+    fn embedded(patterns: &[FilenamePattern]) -> Option<Self> {
+        let (path, contents) = embedded::FIRMWARE?;
+        let filename = path.rsplit('/').next().unwrap_or(path).to_string();
+        Some(Self {
+            source: FirmwareSource::Embedded(contents),
+            ..Self::from_filename(filename, patterns)?
+        })
+    }
+
+    /// a firmware file on a server, recognized by the filename at the end of the url
+    fn remote(url: &str, patterns: &[FilenamePattern]) -> Option<Self> {
+        Some(Self {
+            source: FirmwareSource::Remote(url.to_string()),
+            ..Self::from_filename(download::basename(url).to_string(), patterns)?
+        })
+    }
+
+    /// the newest recovery image in RECOVERY_DIR for the hardware, None when none is staged
+    fn recovery(hardware: &[u8]) -> Option<Self> {
+        fs::read_dir(RECOVERY_DIR)
+            .ok()?
+            .filter_map(|file| file.ok()?.file_name().into_string().ok())
+            .filter(|file_name| file_name.ends_with(".srec"))
+            .filter_map(|file_name| Self::from_filename(file_name, &[]))
+            .filter(|file| file.version.get_hardware() == hardware)
+            .max_by_key(|file| file.version.get_software_version())
+            .map(|file| Self {
+                source: FirmwareSource::Recovery,
+                ..file
+            })
+    }
+
+    /// where the firmware is read from, a symlink in the firmware folder is followed to the file it points to
+    fn location(&self) -> String {
+        match &self.source {
+            FirmwareSource::Directory => {
+                let path = format!("{}{}", FIRMWARE_DIR, self.filename);
+                match fs::canonicalize(&path) {
+                    Ok(target) if target.to_string_lossy() != path => {
+                        format!("{} -> {}", path, target.display())
+                    }
+                    _ => path,
+                }
+            }
+            FirmwareSource::Embedded(_) => format!("{} embedded in the binary", self.filename),
+            FirmwareSource::Remote(url) => url.clone(),
+            FirmwareSource::Recovery => format!("{}{}", RECOVERY_DIR, self.filename),
+        }
+    }
+
+    /// read the srec contents of the firmware
+    fn read(&self) -> std::io::Result<String> {
+        match &self.source {
+            FirmwareSource::Directory => {
+                fs::read_to_string(format!("{}{}", FIRMWARE_DIR, self.filename))
+            }
+            FirmwareSource::Embedded(contents) => Ok(contents.to_string()),
+            FirmwareSource::Remote(url) => download::fetch(url).map_err(io::Error::other),
+            FirmwareSource::Recovery => {
+                fs::read_to_string(format!("{}{}", RECOVERY_DIR, self.filename))
+            }
+        }
+    }
+
+    /// the expected sha256 of the firmware from its `<firmware>.sha256` sidecar, None when there is no sidecar \
+    /// the sidecar holds the hex digest, optionally followed by the filename like sha256sum writes it
+    fn expected_sha256(&self) -> Option<String> {
+        match &self.source {
+            FirmwareSource::Directory => {
+                fs::read_to_string(format!("{}{}.sha256", FIRMWARE_DIR, self.filename))
+                    .ok()
+                    .map(|sidecar| {
+                        sidecar
+                            .split_whitespace()
+                            .next()
+                            .unwrap_or_default()
+                            .to_ascii_lowercase()
+                    })
+            }
+            FirmwareSource::Embedded(_) | FirmwareSource::Remote(_) | FirmwareSource::Recovery => {
+                None
+            }
+        }
+    }
+}
+
+impl Display for FirmwareFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.filename == self.version.as_filename() {
+            write!(f, "{}", self.filename)
+        } else {
+            write!(f, "{} ({})", self.filename, self.version.as_string())
+        }
+    }
+}
+
+/// the software part of a firmware version, ordered by major, then minor, then patch so 1.0.10 < 1.1.0
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct SoftwareVersion {
+    major: u8,
+    minor: u8,
+    patch: u8,
+}
+
+impl SoftwareVersion {
+    /// the version a module reports when its firmware has been wiped
+    const BLANK: Self = Self {
+        major: 255,
+        minor: 255,
+        patch: 255,
+    };
+
+    /// parse a version like 1.2.3 or 1-2-3
+    fn parse(version: &str) -> Option<Self> {
+        let mut numbers = version
+            .split(['.', '-'])
+            .map(|number| number.parse::<u8>().ok());
+        let version = Self {
+            major: numbers.next()??,
+            minor: numbers.next()??,
+            patch: numbers.next()??,
+        };
+        numbers.next().is_none().then_some(version)
+    }
+
+    /// whether the firmware of the module has been wiped, 0.0.0 is a real version and a module that was never
+    /// programmed is recognized by FirmwareVersion::is_factory_blank
+    fn is_blank(&self) -> bool {
+        *self == Self::BLANK
+    }
+}
+
+/// written in the --version-style, dotted until it is set
+impl Display for SoftwareVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let separator = VERSION_STYLE
+            .get()
+            .copied()
+            .unwrap_or(VersionStyle::Dotted)
+            .separator();
+        write!(
+            f,
+            "{}{}{}{}{}",
+            self.major, separator, self.minor, separator, self.patch
+        )
+    }
+}
+
+impl Ord for SoftwareVersion {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.major
+            .cmp(&other.major)
+            .then(self.minor.cmp(&other.minor))
+            .then(self.patch.cmp(&other.patch))
+    }
+}
+
+impl PartialOrd for SoftwareVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 enum CommandArg {
     Scan,
     Update,
     Overwrite,
+    Sync,
+    Benchmark,
+    Diff,
 }
 
 //impl display to make sure we don't have capital letters, as the don't match the commands
@@ -121,6 +1073,9 @@ impl Display for CommandArg {
                 Self::Scan => "scan",
                 Self::Update => "update",
                 Self::Overwrite => "overwrite",
+                Self::Sync => "sync",
+                Self::Benchmark => "benchmark",
+                Self::Diff => "diff",
             }
         )
     }
@@ -129,293 +1084,698 @@ impl Display for CommandArg {
 enum UploadError {
     FirmwareCorrupted(u8),
     FirmwareUntouched(u8),
+    /// the firmware got corrupted and --recover-on-corruption flashed this recovery image instead
+    Recovered(u8, FirmwareVersion),
+    /// stopped through the cancel flag, a module that was already wiped is marked as such for the next run to restore
+    Cancelled(u8),
 }
 
-#[repr(usize)]
-#[derive(Copy, Clone)]
-enum ControllerTypes {
-    ModulineIV = 9,
-    ModulineMini = 5,
-    ModulineDisplay = 3,
-}
+impl UploadError {
+    /// what went wrong, for the json errors of --json
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::FirmwareCorrupted(_) => "firmware_corrupted",
+            Self::FirmwareUntouched(_) => "firmware_untouched",
+            Self::Recovered(..) => "recovered",
+            Self::Cancelled(_) => "cancelled",
+        }
+    }
 
-impl ControllerTypes {
-    fn get_empty_modules_file(&self) -> String {
+    /// the slot the upload failed on
+    fn slot(&self) -> u8 {
         match self {
-            Self::ModulineIV => String::from(
-                ":::::::
-:::::::
-:::::::
-:::::::",
-            ),
-            Self::ModulineMini => String::from(
-                ":::
-:::
-:::
-:::",
-            ),
-            Self::ModulineDisplay => String::from(
-                ":
-:
-:
-:",
-            ),
+            Self::FirmwareCorrupted(slot)
+            | Self::FirmwareUntouched(slot)
+            | Self::Recovered(slot, _)
+            | Self::Cancelled(slot) => *slot,
         }
     }
 }
 
-struct Module {
-    slot: u8,
-    spidev: Spidev,
-    interrupt: AsyncLineEventHandle,
-    firmware: FirmwareVersion,
-    manufacturer: u32,
-    qr_front: u32,
-    qr_back: u32,
+/// how a software version is written, 1.2.3 or 1-2-3 like the software part of a firmware filename, set with
+/// --version-style
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum VersionStyle {
+    Dotted,
+    Dashed,
 }
 
-impl Module {
-    /// construct a new module at the given slot for the given controller type
-    async fn new(slot: u8, controller: &ControllerTypes) -> Option<Self> {
-        //get the spidev
-        let (mut spidev, interrupt) = match controller {
-            //get the Interrupt GPIO and the spidev
-            ControllerTypes::ModulineIV => match slot {
-                1 => (
-                    Spidev::new(
-                        File::open("/dev/spidev1.0")
-                            .map_err(|_| eprintln!("Could not get slot 1 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip0", 6, slot)?,
-                ),
-                2 => (
-                    Spidev::new(
-                        File::open("/dev/spidev1.1")
-                            .map_err(|_| eprintln!("Could not get slot 2 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip4", 20, slot)?,
-                ),
-                3 => (
-                    Spidev::new(
-                        File::open("/dev/spidev2.0")
-                            .map_err(|_| eprintln!("Could not get slot 3 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip0", 7, slot)?,
-                ),
-                4 => (
-                    Spidev::new(
-                        File::open("/dev/spidev2.1")
-                            .map_err(|_| eprintln!("Could not get slot 4 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip4", 21, slot)?,
-                ),
-                5 => (
-                    Spidev::new(
-                        File::open("/dev/spidev2.2")
-                            .map_err(|_| eprintln!("Could not get slot 5 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip4", 1, slot)?,
-                ),
-                6 => (
-                    Spidev::new(
-                        File::open("/dev/spidev2.3")
-                            .map_err(|_| eprintln!("Could not get slot 6 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip3", 26, slot)?,
-                ),
-                7 => (
-                    Spidev::new(
-                        File::open("/dev/spidev0.0")
-                            .map_err(|_| eprintln!("Could not get slot 7 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip2", 19, slot)?,
-                ),
-                8 => (
-                    Spidev::new(
-                        File::open("/dev/spidev0.1")
-                            .map_err(|_| eprintln!("Could not get slot 8 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip2", 22, slot)?,
-                ),
-                _ => {
-                    eprintln!(
-                        "For the Moduline IV, slot should be a value from 1-8 but it was {}",
-                        slot
-                    );
-                    return None;
-                }
-            },
-            ControllerTypes::ModulineMini => match slot {
-                1 => (
-                    Spidev::new(
-                        File::open("/dev/spidev1.0")
-                            .map_err(|_| eprintln!("Could not get slot 1 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip0", 10, slot)?,
-                ),
-                2 => (
-                    Spidev::new(
-                        File::open("/dev/spidev1.1")
-                            .map_err(|_| eprintln!("Could not get slot 2 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip0", 5, slot)?,
-                ),
-                3 => (
-                    Spidev::new(
-                        File::open("/dev/spidev2.0")
-                            .map_err(|_| eprintln!("Could not get slot 3 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip3", 26, slot)?,
-                ),
-                4 => (
-                    Spidev::new(
-                        File::open("/dev/spidev2.1")
-                            .map_err(|_| eprintln!("Could not get slot 4 spidev"))
-                            .ok()?,
-                    ),
-                    get_interrupt("/dev/gpiochip2", 19, slot)?,
-                ),
-                _ => {
-                    eprintln!(
-                        "For the Moduline Mini, slot should be a value from 1-4 but it was {}",
-                        slot
-                    );
-                    return None;
-                }
-            },
-            ControllerTypes::ModulineDisplay => {
-                match slot {
-                    1 => (
-                        Spidev::new(
-                            File::open("/dev/spidev1.0")
-                                .map_err(|_| eprintln!("Could not get slot 1 spidev"))
-                                .ok()?,
-                        ),
-                        get_interrupt("/dev/gpiochip3", 5, slot)?,
-                    ),
-                    2 => (
-                        Spidev::new(
-                            File::open("/dev/spidev1.1")
-                                .map_err(|_| eprintln!("Could not get slot 2 spidev"))
-                                .ok()?,
-                        ),
-                        get_interrupt("/dev/gpiochip0", 0, slot)?,
-                    ),
-                    _ => {
-                        eprintln!("For the Moduline Display, slot should be a value from 1-2 but it was {}",slot);
-                        return None;
-                    }
-                }
-            }
-        };
-        spidev
-            .configure(
-                &SpidevOptions::new()
-                    .bits_per_word(8)
-                    .max_speed_hz(2_000_000)
-                    .mode(SpiModeFlags::SPI_MODE_0)
-                    .build(),
-            )
-            .map_err(|_| eprintln!("Could not configure spidev for slot {}", slot))
-            .ok()?;
-        let module = Self {
-            slot,
-            spidev,
-            interrupt,
-            firmware: FirmwareVersion { firmware: [0; 7] },
-            manufacturer: 0,
-            qr_front: 0,
-            qr_back: 0,
-        };
-        module.get_module_info().await
+impl VersionStyle {
+    fn parse(format: &str) -> Option<Self> {
+        match format {
+            "dotted" => Some(Self::Dotted),
+            "dashed" => Some(Self::Dashed),
+            _ => None,
+        }
     }
 
-    /// get information from the module like firmware, manufacture, qr codes
-    async fn get_module_info(mut self) -> Option<Self> {
-        let mut tx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
-        let mut rx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
-
-        match self
-            .spidev
-            .transfer(&mut SpidevTransfer::write(&DUMMY_MESSAGE))
-        {
-            Ok(()) => (),
-            Err(_) => return None,
+    fn separator(self) -> char {
+        match self {
+            Self::Dotted => '.',
+            Self::Dashed => '-',
         }
+    }
+}
 
-        self.reset_module(true);
+/// which QR code of a module is used to select it and shown by scan, set with --qr-side
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum QrSide {
+    Front,
+    Back,
+    Both,
+}
 
-        //give module time to reset
-        time::sleep(Duration::from_millis(200)).await;
+impl QrSide {
+    fn parse(side: &str) -> Option<Self> {
+        match side {
+            "front" => Some(Self::Front),
+            "back" => Some(Self::Back),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
 
-        self.reset_module(false);
+    /// whether the QR code on this side of the module is qr
+    fn matches(self, module: &Module, qr: u32) -> bool {
+        match self {
+            Self::Front => module.qr_front == qr,
+            Self::Back => module.qr_back == qr,
+            Self::Both => module.qr_front == qr || module.qr_back == qr,
+        }
+    }
 
-        time::sleep(Duration::from_millis(200)).await;
+    /// the values of the sides to show out of the front and back value
+    fn columns<T>(self, [front, back]: [T; 2]) -> impl Iterator<Item = T> {
+        let (show_front, show_back) = match self {
+            Self::Front => (true, false),
+            Self::Back => (false, true),
+            Self::Both => (true, true),
+        };
+        show_front
+            .then_some(front)
+            .into_iter()
+            .chain(show_back.then_some(back))
+    }
+}
+
+impl Display for QrSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Front => "front QR",
+                Self::Back => "back QR",
+                Self::Both => "front or back QR",
+            }
+        )
+    }
+}
+
+/// a frame received from the module bootloader that passed its checksum
+/// ``` text
+/// [0] command  [1] length  [2] command echo  [3..6] unused  [6..checksum] payload  [checksum]
+/// ```
+/// the payload of a firmware line response starts with the line number and the status, which get their own fields
+struct BootResponse<'a> {
+    command: u8,
+    command_echo: u8,
+    /// the line number a firmware line response answers for
+    line: u16,
+    /// 1 when the module received the firmware line correctly
+    status: u8,
+    /// the bytes between the header and the checksum
+    payload: &'a [u8],
+}
+
+impl<'a> BootResponse<'a> {
+    /// parse a frame of BOOTMESSAGE_LENGTH bytes, which has its checksum in the last byte
+    fn parse(rx_buf: &'a [u8], algorithm: Checksum) -> Option<Self> {
+        Self::parse_with_checksum_at(rx_buf, BOOTMESSAGE_LENGTH - 1, algorithm)
+    }
+
+    /// parse a frame of the length given in its own length byte, the checksum follows the last byte it covers
+    fn parse_sized(rx_buf: &'a [u8], algorithm: Checksum) -> Option<Self> {
+        Self::parse_with_checksum_at(rx_buf, *rx_buf.get(1)? as usize, algorithm)
+    }
+
+    fn parse_with_checksum_at(
+        rx_buf: &'a [u8],
+        checksum: usize,
+        algorithm: Checksum,
+    ) -> Option<Self> {
+        // the index can come from the module, make sure it fits before using it
+        if checksum >= rx_buf.len() {
+            return None;
+        }
+        //the transfer prefills 0xFF, a frame cut short keeps it from the checksum on and passes the checksum by
+        //chance 1 in 256 times, a real frame ending like that is refused and retried like a garbled one
+        if checksum + 1 < rx_buf.len() && rx_buf[checksum..].iter().all(|byte| *byte == 0xFF) {
+            return None;
+        }
+        if rx_buf[checksum] != algorithm.calculate(rx_buf, checksum) {
+            return None;
+        }
+        let header = rx_buf.get(0..9)?;
+        Some(Self {
+            command: header[0],
+            command_echo: header[2],
+            line: u16::from_be_bytes([header[6], header[7]]),
+            status: header[8],
+            payload: rx_buf.get(6..checksum).unwrap_or_default(),
+        })
+    }
+
+    /// whether the frame is an answer to the message type, either the command or its echo has to match
+    fn answers(&self, message_type: u8) -> bool {
+        self.command == message_type || self.command_echo == message_type
+    }
+}
+
+/// how hard the current firmware line is to get across, by the errors in a row, the upload fails past 10
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum UploadHealth {
+    /// no more than the odd retry
+    Clean,
+    /// several errors in a row
+    Struggling,
+    /// close to giving up on the upload
+    Failing,
+}
+
+impl UploadHealth {
+    fn of(firmware_error_counter: u8) -> Self {
+        match firmware_error_counter {
+            0..=3 => Self::Clean,
+            4..=7 => Self::Struggling,
+            _ => Self::Failing,
+        }
+    }
+}
+
+/// how the module answered a firmware line, a miss points at a logic error while no response points at a dead module or bus
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum LineResponse {
+    /// the module received the expected line correctly
+    Ack,
+    /// the module answered for the expected line but didn't receive it correctly
+    Nak,
+    /// the module answered for the line that was just sent instead of the one before it
+    WrongLine(u16),
+    /// the module answered for neither the expected line nor the line just sent, it lost track of the upload
+    Desync(u16),
+    /// the response didn't pass the checksum
+    Corrupt,
+    /// nothing drove the bus, every byte of the response is the same
+    NoResponse,
+}
+
+impl LineResponse {
+    /// classify the response to the line sent before the current one
+    fn of(rx_buf: &[u8], checksum: Checksum, expected_line: usize, current_line: usize) -> Self {
+        if rx_buf.iter().all(|byte| *byte == rx_buf[0]) {
+            return Self::NoResponse;
+        }
+        let Some(response) = BootResponse::parse(rx_buf, checksum) else {
+            return Self::Corrupt;
+        };
+        if response.line as usize != expected_line {
+            if response.line as usize != current_line {
+                return Self::Desync(response.line);
+            }
+            return Self::WrongLine(response.line);
+        }
+        if response.status != 1 {
+            return Self::Nak;
+        }
+        Self::Ack
+    }
+}
+
+/// the controller as described by the device tree hardware string, for example "GOcontroll Moduline IV V3.06"
+struct ControllerInfo {
+    family: ControllerTypes,
+    /// the hardware revision without the leading V, if the string has one
+    revision: Option<String>,
+}
+
+impl ControllerInfo {
+    /// parse the hardware string, the family is the word after Moduline and the revision the first word like V3.06
+    fn parse(hardware: &str) -> Result<Self, String> {
+        //device tree strings end in a nul byte
+        let hardware = hardware.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        let mut words = hardware
+            .split_whitespace()
+            .skip_while(|word| *word != "Moduline")
+            .skip(1);
+        let family = match words.next() {
+            Some("IV") => ControllerTypes::ModulineIV,
+            Some("Mini") => ControllerTypes::ModulineMini,
+            Some("Display") => ControllerTypes::ModulineDisplay,
+            Some(family) => {
+                return Err(format!(
+                    "unrecognized Moduline family \"{}\" in \"{}\"",
+                    family, hardware
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "\"{}\" is not a supported GOcontroll Moduline product",
+                    hardware
+                ))
+            }
+        };
+        let revision = words
+            .find_map(|word| {
+                word.strip_prefix(['V', 'v'])
+                    .filter(|revision| revision.starts_with(|c: char| c.is_ascii_digit()))
+            })
+            .map(str::to_string);
+        Ok(Self { family, revision })
+    }
+}
+
+#[repr(usize)]
+#[derive(Copy, Clone)]
+enum ControllerTypes {
+    ModulineIV = 9,
+    ModulineMini = 5,
+    ModulineDisplay = 3,
+}
+
+impl Display for ControllerTypes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::ModulineIV => "Moduline IV",
+                Self::ModulineMini => "Moduline Mini",
+                Self::ModulineDisplay => "Moduline Display",
+            }
+        )
+    }
+}
+
+impl ControllerTypes {
+    /// a short name of the controller for compact output
+    fn short_name(&self) -> &'static str {
+        match self {
+            Self::ModulineIV => "IV",
+            Self::ModulineMini => "Mini",
+            Self::ModulineDisplay => "Display",
+        }
+    }
+
+    fn slot_count(&self) -> u8 {
+        *self as u8 - 1
+    }
+
+    /// the spidev and interrupt line of every slot, in slot order
+    fn slot_mapping(&self) -> &'static [SlotMapping] {
+        match self {
+            Self::ModulineIV => &MODULINE_IV_SLOTS,
+            Self::ModulineMini => &MODULINE_MINI_SLOTS,
+            Self::ModulineDisplay => &MODULINE_DISPLAY_SLOTS,
+        }
+    }
+
+    /// check that the hand written slot mapping has a slot for every slot number and that no two slots share a spidev
+    /// or interrupt line, which would flash the module in another slot than the one that was chosen
+    fn check_slot_mapping(&self) -> Result<(), String> {
+        let mapping = self.slot_mapping();
+        if mapping.len() != self.slot_count() as usize {
+            return Err(format!(
+                "the {} slot mapping has {} slots instead of {}",
+                self,
+                mapping.len(),
+                self.slot_count()
+            ));
+        }
+        for (i, slot) in mapping.iter().enumerate() {
+            for (j, other) in mapping.iter().enumerate().skip(i + 1) {
+                if slot.spidev == other.spidev {
+                    return Err(format!(
+                        "the {} slot mapping puts slots {} and {} on the same spidev {}",
+                        self,
+                        i + 1,
+                        j + 1,
+                        slot.spidev
+                    ));
+                }
+                if slot.gpiochip == other.gpiochip && slot.line == other.line {
+                    return Err(format!(
+                        "the {} slot mapping puts slots {} and {} on the same interrupt line {} {}",
+                        self,
+                        i + 1,
+                        j + 1,
+                        slot.gpiochip,
+                        slot.line
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the slot labels used when slot_labels isn't set in /etc/go-modules.toml, just the logical slot numbers \
+    /// whether those match what is printed on the enclosures hasn't been checked for every controller, so without
+    /// slot_labels a label is the slot number and nothing changes
+    fn default_slot_labels(&self) -> Vec<String> {
+        (1..=self.slot_count())
+            .map(|slot| slot.to_string())
+            .collect()
+    }
+
+    fn get_empty_modules_file(&self) -> String {
+        match self {
+            Self::ModulineIV => String::from(
+                ":::::::
+:::::::
+:::::::
+:::::::",
+            ),
+            Self::ModulineMini => String::from(
+                ":::
+:::
+:::
+:::",
+            ),
+            Self::ModulineDisplay => String::from(
+                ":
+:
+:
+:",
+            ),
+        }
+    }
+}
+
+/// the spidev of a slot and the gpio line its module interrupt is on
+struct SlotMapping {
+    spidev: &'static str,
+    gpiochip: &'static str,
+    line: u32,
+}
+
+/// the slots of the Moduline IV, in slot order
+const MODULINE_IV_SLOTS: [SlotMapping; 8] = [
+    SlotMapping {
+        spidev: "/dev/spidev1.0",
+        gpiochip: "/dev/gpiochip0",
+        line: 6,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev1.1",
+        gpiochip: "/dev/gpiochip4",
+        line: 20,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev2.0",
+        gpiochip: "/dev/gpiochip0",
+        line: 7,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev2.1",
+        gpiochip: "/dev/gpiochip4",
+        line: 21,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev2.2",
+        gpiochip: "/dev/gpiochip4",
+        line: 1,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev2.3",
+        gpiochip: "/dev/gpiochip3",
+        line: 26,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev0.0",
+        gpiochip: "/dev/gpiochip2",
+        line: 19,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev0.1",
+        gpiochip: "/dev/gpiochip2",
+        line: 22,
+    },
+];
+
+/// the slots of the Moduline Mini, in slot order
+const MODULINE_MINI_SLOTS: [SlotMapping; 4] = [
+    SlotMapping {
+        spidev: "/dev/spidev1.0",
+        gpiochip: "/dev/gpiochip0",
+        line: 10,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev1.1",
+        gpiochip: "/dev/gpiochip0",
+        line: 5,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev2.0",
+        gpiochip: "/dev/gpiochip3",
+        line: 26,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev2.1",
+        gpiochip: "/dev/gpiochip2",
+        line: 19,
+    },
+];
+
+/// the slots of the Moduline Display, in slot order
+const MODULINE_DISPLAY_SLOTS: [SlotMapping; 2] = [
+    SlotMapping {
+        spidev: "/dev/spidev1.0",
+        gpiochip: "/dev/gpiochip3",
+        line: 5,
+    },
+    SlotMapping {
+        spidev: "/dev/spidev1.1",
+        gpiochip: "/dev/gpiochip0",
+        line: 0,
+    },
+];
+
+struct Module {
+    slot: u8,
+    spidev: Spi,
+    /// None if the interrupt line could not be requested, the module then falls back to fixed waiting times
+    interrupt: Option<Interrupt>,
+    /// the frame checksum the bootloader answered the info request with
+    checksum: Checksum,
+    /// the bootloader protocol version from the info response, 0 for the legacy protocol
+    boot_protocol: u8,
+    /// the info payload bytes after the bootloader protocol, which have no documented meaning yet
+    extra_info: Vec<u8>,
+    firmware: FirmwareVersion,
+    manufacturer: u32,
+    qr_front: u32,
+    qr_back: u32,
+    /// how long the last wipe of this run took, None before a wipe or when timed mode couldn't measure it
+    wipe_time: Option<Duration>,
+}
+
+/// what the modules file holds about a module, without the spi and interrupt handles of a Module
+#[derive(Clone)]
+struct ModuleRecord {
+    slot: u8,
+    firmware: FirmwareVersion,
+    manufacturer: u32,
+    qr_front: u32,
+    qr_back: u32,
+}
+
+impl Module {
+    fn record(&self) -> ModuleRecord {
+        ModuleRecord {
+            slot: self.slot,
+            firmware: self.firmware,
+            manufacturer: self.manufacturer,
+            qr_front: self.qr_front,
+            qr_back: self.qr_back,
+        }
+    }
+
+    /// the extra info bytes as hex, None when they are all 0 like on the modules seen so far
+    fn extra_info_hex(&self) -> Option<String> {
+        self.extra_info.iter().any(|byte| *byte != 0).then(|| {
+            self.extra_info
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect()
+        })
+    }
+
+    /// the name of the manufacturer of the module, None if its id isn't in MANUFACTURERS
+    fn manufacturer_name(&self) -> Option<&str> {
+        known_manufacturer(self.manufacturer)
+    }
+
+    /// construct a new module at the given slot for the given controller type
+    async fn new(slot: u8, controller: &ControllerTypes) -> Option<Self> {
+        Self::open(slot, controller)?.get_module_info().await
+    }
+
+    /// open the spidev and interrupt of a slot without talking to the module, its information is left empty
+    fn open(slot: u8, controller: &ControllerTypes) -> Option<Self> {
+        let Some(mapping) = (slot as usize)
+            .checked_sub(1)
+            .and_then(|index| controller.slot_mapping().get(index))
+        else {
+            print_error(format!(
+                "For the {}, slot should be a value from 1-{} but it was {}",
+                controller,
+                controller.slot_count(),
+                slot
+            ));
+            return None;
+        };
+        //get the spidev and the interrupt GPIO
+        let mut spidev = Spi::open(mapping.spidev)
+            .map_err(|_| print_error(format!("Could not get slot {} spidev", slot_label(slot))))
+            .ok()?;
+        let interrupt = Interrupt::request(mapping.gpiochip, mapping.line, slot);
+        spidev
+            .configure(spi_speed())
+            .map_err(|_| {
+                print_error(format!(
+                    "Could not configure spidev for slot {}",
+                    slot_label(slot)
+                ))
+            })
+            .ok()?;
+        if interrupt.is_none() && !transport::PRETEND {
+            print_warning(format!(
+                "Slot {} interrupt unavailable, falling back to timed mode",
+                slot_label(slot)
+            ));
+        }
+        Some(Self {
+            slot,
+            spidev,
+            interrupt,
+            checksum: Checksum::WrappingAdd,
+            boot_protocol: 0,
+            extra_info: Vec::new(),
+            firmware: FirmwareVersion { firmware: [0; 7] },
+            manufacturer: 0,
+            qr_front: 0,
+            qr_back: 0,
+            wipe_time: None,
+        })
+    }
+
+    /// get information from the module like firmware, manufacture, qr codes \
+    /// the payload of the info response, the frame bytes from 6 on:
+    /// ``` text
+    /// [0..7] firmware  [7..11] manufacturer  [11..15] front qr  [15..19] back qr  [19] bootloader protocol  [20..39] extra
+    /// ```
+    /// no bootloader is known to fill in the extra bytes, like a build date or serial, they are kept as they are so
+    /// a module that does fill them shows up in the scan
+    async fn get_module_info(mut self) -> Option<Self> {
+        let rx_buf = self.enter_bootloader().await?;
+        let response =
+            BootResponse::parse(&rx_buf, self.checksum).filter(|response| response.answers(9))?;
+        let info = response.payload;
+
+        self.firmware = FirmwareVersion {
+            firmware: clone_into_array(&info[0..7]),
+        };
+        self.manufacturer = u32::from_be_bytes(clone_into_array(&info[7..11]));
+        self.qr_front = u32::from_be_bytes(clone_into_array(&info[11..15]));
+        self.qr_back = u32::from_be_bytes(clone_into_array(&info[15..19]));
+        //the byte after the qr codes is unused by the legacy bootloaders, which leave it 0
+        self.boot_protocol = info.get(19).copied().unwrap_or(0);
+        self.extra_info = info.get(20..).unwrap_or_default().to_vec();
+        if self.boot_protocol > BOOT_PROTOCOL {
+            print_warning(format!(
+                "Warning: slot {} reports bootloader protocol {}, this version of go-modules only knows up to {}, using the legacy protocol",
+                slot_label(self.slot),
+                self.boot_protocol,
+                BOOT_PROTOCOL
+            ));
+        }
+        Some(self)
+    }
+
+    /// reset the module into its bootloader and ask for its info, returns the info frame if the module answered it
+    async fn enter_bootloader(&mut self) -> Option<[u8; BOOTMESSAGE_LENGTH + 1]> {
+        let mut tx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+        let mut rx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+
+        match self.spidev.write(&DUMMY_MESSAGE) {
+            Ok(()) => (),
+            Err(_) => return None,
+        }
+
+        self.pulse_reset().await;
 
         tx_buf[0] = 9;
         tx_buf[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
         tx_buf[2] = 9;
-        tx_buf[BOOTMESSAGE_LENGTH - 1] = calculate_checksum(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+        //the checksum of the bootloader isn't known before it answers
+        tx_buf[BOOTMESSAGE_LENGTH - 1] =
+            Checksum::WrappingAdd.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
 
-        match self
-            .spidev
-            .transfer(&mut SpidevTransfer::read_write(&tx_buf, &mut rx_buf))
-        {
+        match self.spidev.transfer(&tx_buf, &mut rx_buf) {
             Ok(()) => (),
             Err(_) => return None,
         }
-
-        if rx_buf[BOOTMESSAGE_LENGTH - 1] != calculate_checksum(&rx_buf, BOOTMESSAGE_LENGTH - 1)
-            || (rx_buf[0] != 9 && rx_buf[2] != 9)
-        {
+        //all zeros even passes the wrapping add checksum, it only fails on not answering the info request
+        let mut stuck = STUCK_SLOTS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if rx_buf.iter().all(|byte| *byte == rx_buf[0]) && matches!(rx_buf[0], 0x00 | 0xFF) {
+            stuck.insert(self.slot, rx_buf[0]);
             return None;
         }
+        stuck.remove(&self.slot);
+        drop(stuck);
+        let checksum = Checksum::detect(&rx_buf, BOOTMESSAGE_LENGTH - 1)?;
+        self.checksum = checksum;
+        BootResponse::parse(&rx_buf, checksum)
+            .is_some_and(|response| response.answers(9))
+            .then_some(rx_buf)
+    }
 
-        self.firmware = FirmwareVersion {
-            firmware: clone_into_array(rx_buf.get(6..13).unwrap()),
-        };
-        self.manufacturer = u32::from_be_bytes(clone_into_array(rx_buf.get(13..17).unwrap()));
-        self.qr_front = u32::from_be_bytes(clone_into_array(rx_buf.get(17..21).unwrap()));
-        self.qr_back = u32::from_be_bytes(clone_into_array(rx_buf.get(21..25).unwrap()));
-        Some(self)
+    /// check right before flashing that the module the scan found still answers in its slot, the prompts in between
+    /// leave plenty of time to pull or swap a module, which would otherwise only show as a failed upload after the wipe
+    async fn check_still_present(&mut self) -> Result<(), String> {
+        let rx_buf = self.enter_bootloader().await.ok_or_else(|| {
+            format!(
+                "module no longer present in slot {} (was it removed?)",
+                slot_label(self.slot)
+            )
+        })?;
+        let info = BootResponse::parse(&rx_buf, self.checksum)
+            .map(|response| response.payload)
+            .unwrap_or_default();
+        if info.get(0..7) != Some(&self.firmware.firmware[..])
+            || info.get(11..15) != Some(&self.qr_front.to_be_bytes()[..])
+        {
+            return Err(format!(
+                "slot {} holds another module than the scan found (was it swapped?), scan again",
+                slot_label(self.slot)
+            ));
+        }
+        Ok(())
     }
 
     /// switch the reset gpio for the module to the given state
     fn reset_module(&self, state: bool) {
         if state {
-            _ = std::fs::write(
-                format!("/sys/class/leds/ResetM-{}/brightness", self.slot),
-                "255",
-            );
+            _ = std::fs::write(reset_path(self.slot), "255");
         } else {
-            _ = std::fs::write(
-                format!("/sys/class/leds/ResetM-{}/brightness", self.slot),
-                "0",
-            );
+            _ = std::fs::write(reset_path(self.slot), "0");
         }
     }
 
-    async fn wipe_module_error(&mut self) {
-        let mut tx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
-        match self
-            .spidev
-            .transfer(&mut SpidevTransfer::write(&DUMMY_MESSAGE))
-        {
-            Ok(()) => (),
-            Err(_) => return,
-        }
-
+    /// reset the module and give it time to start again
+    async fn pulse_reset(&self) {
         self.reset_module(true);
 
         //give module time to reset
@@ -424,6 +1784,27 @@ impl Module {
         self.reset_module(false);
 
         time::sleep(Duration::from_millis(200)).await;
+    }
+
+    /// throw away interrupt events that queued up earlier
+    async fn clear_interrupts(&mut self) {
+        if let Some(interrupt) = self.interrupt.as_mut() {
+            //this is super scuffed but for some reason it queues up events, so when in earlier parts the interrupt happens it fills the queue, causing it to skip the memory wipe interrupt and fail
+            while timeout(Duration::from_millis(1), interrupt.next())
+                .await
+                .is_ok()
+            {}
+        }
+    }
+
+    async fn wipe_module_error(&mut self, _permit: &WipePermit) {
+        let mut tx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+        match self.spidev.write(&DUMMY_MESSAGE) {
+            Ok(()) => (),
+            Err(_) => return,
+        }
+
+        self.pulse_reset().await;
 
         //wipe the old firmware and set the new software version no err_n_restart_services from this point on, errors lead to corrupt firmware.
         tx_buf[0] = 29;
@@ -432,24 +1813,207 @@ impl Module {
         tx_buf[6] = 255;
         tx_buf[7] = 255;
         tx_buf[8] = 255;
-        tx_buf[BOOTMESSAGE_LENGTH - 1] = calculate_checksum(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+        tx_buf[BOOTMESSAGE_LENGTH - 1] = self.checksum.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
 
-        //this is super scuffed but for some reason it queues up events, so when in earlier parts the interrupt happens it fills the queue, causing it to skip the memory wipe interrupt and fail
-        while let Ok(_) = timeout(Duration::from_millis(1), self.interrupt.next()).await {
-            ()
-        }
+        self.clear_interrupts().await;
 
         //register the interrupt waiter
-        let interrupt = self.interrupt.next();
-        match self.spidev.transfer(&mut SpidevTransfer::write(&tx_buf)) {
+        let interrupt = self.interrupt.as_mut().map(|interrupt| interrupt.next());
+        match self.spidev.write(&tx_buf) {
             Ok(()) => (),
             Err(err) => {
-                eprintln!("Error: failed spi transfer {}", err);
+                print_error(format!("Error: failed spi transfer {}", err));
                 return;
             }
         }
 
-        _ = timeout(Duration::from_millis(3500), interrupt).await;
+        wait_interrupt(interrupt, Duration::from_millis(3500)).await;
+    }
+
+    /// overwrite_module, repeating the whole wipe and flash up to --auto-retry-corrupted times when it corrupts the firmware
+    async fn overwrite_module_retrying(
+        &mut self,
+        permit: &WipePermit,
+        new_firmware: &FirmwareFile,
+        multi_progress: MultiProgress,
+        style: ProgressStyle,
+        options: &Options,
+        cancel: &AtomicBool,
+    ) -> Result<(), UploadError> {
+        let mut retry = 0;
+        let result = loop {
+            let result = self
+                .overwrite_module(
+                    permit,
+                    new_firmware,
+                    multi_progress.clone(),
+                    style.clone(),
+                    options,
+                    cancel,
+                )
+                .await;
+            if result.is_ok() {
+                record_flashed(self.slot, new_firmware, self.wipe_time);
+                COMPLETED
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push(ModuleRecord {
+                        firmware: new_firmware.version,
+                        ..self.record()
+                    });
+                if let Some(command) = &options.post_flash {
+                    self.run_post_flash(command, new_firmware, &multi_progress, options)
+                        .await;
+                }
+            }
+            if !matches!(result, Err(UploadError::FirmwareCorrupted(_)))
+                || retry == options.auto_retry_corrupted
+                || cancel.load(Ordering::Relaxed)
+            {
+                break result;
+            }
+            retry += 1;
+            multi_progress.suspend(|| {
+                print_warning(format!(
+                    "Warning: firmware corrupted on slot {}, flashing it again ({} of {})",
+                    slot_label(self.slot),
+                    retry,
+                    options.auto_retry_corrupted
+                ))
+            });
+            //the failed upload left the bootloader mid transfer, start over from a reset like the scan does
+            if self.enter_bootloader().await.is_none() {
+                break result;
+            }
+        };
+        if !options.recover_on_corruption
+            || !matches!(result, Err(UploadError::FirmwareCorrupted(_)))
+            || cancel.load(Ordering::Relaxed)
+        {
+            return result;
+        }
+        match self
+            .flash_recovery(permit, new_firmware, multi_progress, style, options, cancel)
+            .await
+        {
+            Some(recovery) => Err(UploadError::Recovered(self.slot, recovery)),
+            None => result,
+        }
+    }
+
+    /// flash the recovery image for the hardware of new_firmware after its upload corrupted the module, returns the
+    /// version of the recovery image when the module runs it
+    async fn flash_recovery(
+        &mut self,
+        permit: &WipePermit,
+        new_firmware: &FirmwareFile,
+        multi_progress: MultiProgress,
+        style: ProgressStyle,
+        options: &Options,
+        cancel: &AtomicBool,
+    ) -> Option<FirmwareVersion> {
+        let Some(recovery) = FirmwareFile::recovery(new_firmware.version.get_hardware()) else {
+            multi_progress.suspend(|| {
+                print_warning(format!(
+                    "Warning: firmware corrupted on slot {}, there is no recovery image for {} in {}",
+                    slot_label(self.slot),
+                    describe_hardware(&new_firmware.version),
+                    RECOVERY_DIR
+                ))
+            });
+            return None;
+        };
+        multi_progress.suspend(|| {
+            print_warning(format!(
+                "Warning: firmware corrupted on slot {}, flashing the recovery image {}",
+                slot_label(self.slot),
+                recovery
+            ))
+        });
+        if self.enter_bootloader().await.is_none() {
+            multi_progress.suspend(|| {
+                print_error(format!(
+                    "Error: slot {} did not answer before flashing the recovery image",
+                    slot_label(self.slot)
+                ))
+            });
+            return None;
+        }
+        match self
+            .overwrite_module(
+                permit,
+                &recovery,
+                multi_progress.clone(),
+                style,
+                options,
+                cancel,
+            )
+            .await
+        {
+            Ok(()) => {
+                record_flashed(self.slot, &recovery, self.wipe_time);
+                COMPLETED
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push(ModuleRecord {
+                        firmware: recovery.version,
+                        ..self.record()
+                    });
+                Some(recovery.version)
+            }
+            Err(_) => {
+                multi_progress.suspend(|| {
+                    print_error(format!(
+                        "Error: the recovery image {} failed on slot {} too",
+                        recovery,
+                        slot_label(self.slot)
+                    ))
+                });
+                None
+            }
+        }
+    }
+
+    /// run the --post-flash command for the flash of new_firmware that just succeeded, the module still holds the old
+    /// firmware version
+    async fn run_post_flash(
+        &self,
+        command: &str,
+        new_firmware: &FirmwareFile,
+        multi_progress: &MultiProgress,
+        options: &Options,
+    ) {
+        let mut post_flash = Command::new("sh");
+        post_flash
+            .arg("-c")
+            .arg(command)
+            .env("GO_MODULES_SLOT", self.slot.to_string())
+            .env("GO_MODULES_SLOT_LABEL", slot_label(self.slot))
+            .env("GO_MODULES_OLD_VERSION", self.firmware.as_string())
+            .env("GO_MODULES_NEW_VERSION", new_firmware.version.as_string())
+            .env("GO_MODULES_QR_FRONT", self.qr_front.to_string())
+            .env("GO_MODULES_QR_BACK", self.qr_back.to_string());
+        //the other modules keep flashing while the command runs
+        let status = task::spawn_blocking(move || post_flash.status())
+            .await
+            .map_err(io::Error::other)
+            .and_then(|status| status);
+        let failure = match status {
+            Ok(status) if status.success() => return,
+            Ok(status) => format!("exited with {}", status),
+            Err(err) => format!("could not be run: {}", err),
+        };
+        let message = format!(
+            "the post flash command for slot {} {}",
+            slot_label(self.slot),
+            failure
+        );
+        if options.post_flash_required {
+            multi_progress.suspend(|| print_error(format!("Error: {}", message)));
+            POST_FLASH_FAILED.store(true, Ordering::Relaxed);
+        } else {
+            multi_progress.suspend(|| print_warning(format!("Warning: {}", message)));
+        }
     }
 
     /// Overwrite the firmware on a module \
@@ -506,9 +2070,12 @@ impl Module {
     ///```
     async fn overwrite_module(
         &mut self,
-        new_firmware: &FirmwareVersion,
+        permit: &WipePermit,
+        new_firmware: &FirmwareFile,
         multi_progress: MultiProgress,
         style: ProgressStyle,
+        options: &Options,
+        cancel: &AtomicBool,
     ) -> Result<(), UploadError> {
         let mut tx_buf_escape = [0u8; BOOTMESSAGE_LENGTH_CHECK];
         let mut rx_buf_escape = [0u8; BOOTMESSAGE_LENGTH_CHECK];
@@ -517,66 +2084,177 @@ impl Module {
         let mut rx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
 
         //open and read the firmware file
-        let firmware_content_string = match fs::read_to_string(format!(
-            "/lib/firmware/gocontroll/{}",
-            new_firmware.as_filename()
-        )) {
+        let firmware_content_string = match new_firmware.read() {
             Ok(file) => file,
             Err(err) => {
-                eprintln!(
+                print_error(format!(
                     "Error: could not read {}\n{}",
-                    new_firmware.as_filename(),
-                    err
-                );
+                    new_firmware.filename, err
+                ));
                 return Err(UploadError::FirmwareUntouched(self.slot));
             }
         };
+        //a partially copied firmware file would pass as a shorter srec, check it against its sidecar before wiping
+        if let Some(expected) = new_firmware.expected_sha256() {
+            let actual = sha256::hex_digest(firmware_content_string.as_bytes());
+            if actual != expected {
+                print_error(format!(
+                    "Error: {} does not match {}.sha256, expected {} but got {}",
+                    new_firmware.filename, new_firmware.filename, expected, actual
+                ));
+                return Err(UploadError::FirmwareUntouched(self.slot));
+            }
+        }
 
         //upload
-        let lines: Vec<&str> = firmware_content_string.split('\n').collect();
+        let mut lines: Vec<&str> = firmware_content_string.split('\n').collect();
+        //a file ending in a newline leaves an empty element behind the S7 record
+        while lines.last().is_some_and(|line| line.trim().is_empty()) {
+            lines.pop();
+        }
 
-        if lines.len() <= 1 {
-            eprintln!("Error: firmware file corrupt");
+        //the upload indexes into every record, a malformed one has to be caught before the wipe
+        if let Err(err) = check_srec(&lines) {
+            print_error(format!("Error: firmware file corrupt, {}", err));
+            return Err(UploadError::FirmwareUntouched(self.slot));
+        }
+        if options.verbose {
+            let header = srec_header(&lines).unwrap_or_else(|| "none".to_string());
+            multi_progress.suspend(|| {
+                println!(
+                    "slot {}: srec header of {}: {}",
+                    slot_label(self.slot),
+                    new_firmware.filename,
+                    header
+                )
+            });
+        }
+        if let Some(swapped_version) = byte_swapped_header_version(&lines) {
+            let message = format!(
+                "the srec header of {} names {} with every two bytes swapped, its data is probably in the wrong byte order",
+                new_firmware.filename,
+                swapped_version.as_string()
+            );
+            if options.strict {
+                print_error(format!("Error: {}", message));
+                return Err(UploadError::FirmwareUntouched(self.slot));
+            }
+            multi_progress.suspend(|| print_warning(format!("Warning: {}", message)));
+        }
+        //a renamed file claims a version its contents aren't, only detectable when the header names one
+        if let Some(header_version) = srec_header(&lines)
+            .as_deref()
+            .and_then(header_firmware_version)
+            .filter(|version| *version != new_firmware.version)
+        {
+            let message = format!(
+                "{} is named as {} but its srec header names {}",
+                new_firmware.filename,
+                new_firmware.version.as_string(),
+                header_version.as_string()
+            );
+            if options.strict {
+                print_error(format!("Error: {}", message));
+                return Err(UploadError::FirmwareUntouched(self.slot));
+            }
+            multi_progress.suspend(|| print_warning(format!("Warning: {}", message)));
+        }
+        if cancel.load(Ordering::Relaxed) {
+            return Err(UploadError::Cancelled(self.slot));
+        }
+        //the services may have been kept running, anything that opened this module's bus since the startup check
+        //would interleave its frames with the upload
+        let users = spidev_users(self.spidev.path());
+        if !users.is_empty() {
+            print_error(format!(
+                "Error: {} is in use by {}, not wiping the module in slot {}",
+                self.spidev.path(),
+                users.join(", "),
+                slot_label(self.slot)
+            ));
             return Err(UploadError::FirmwareUntouched(self.slot));
         }
         //wipe the old firmware and set the new software version no err_n_restart_services from this point on, errors lead to corrupt firmware.
         tx_buf[0] = 29;
         tx_buf[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
         tx_buf[2] = 29;
-        let sw = new_firmware.get_software();
+        let sw = new_firmware.version.get_software();
         tx_buf[6] = sw[0];
         tx_buf[7] = sw[1];
         tx_buf[8] = sw[2];
-        tx_buf[BOOTMESSAGE_LENGTH - 1] = calculate_checksum(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+        tx_buf[BOOTMESSAGE_LENGTH - 1] = self.checksum.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
 
-        //this is super scuffed but for some reason it queues up events, so when in earlier parts the interrupt happens it fills the queue, causing it to skip the memory wipe interrupt and fail
-        while let Ok(_) = timeout(Duration::from_millis(1), self.interrupt.next()).await {
-            ()
-        }
+        self.clear_interrupts().await;
 
         //register the interrupt waiter
-        let interrupt = self.interrupt.next();
-        match self.spidev.transfer(&mut SpidevTransfer::write(&tx_buf)) {
-            Ok(()) => (),
-            Err(err) => {
-                eprintln!("Error: failed spi transfer {}", err);
+        let interrupt = self.interrupt.as_mut().map(|interrupt| interrupt.next());
+        //the old firmware is only gone once the wipe frame made it, until then sending it again is safe
+        let mut wipe_attempt = 1;
+        while let Err(err) = self.spidev.write(&tx_buf) {
+            if wipe_attempt == WIPE_ATTEMPTS {
+                print_error(format!(
+                    "Error: failed spi transfer {} after {} attempts",
+                    err, wipe_attempt
+                ));
                 return Err(UploadError::FirmwareUntouched(self.slot));
             }
+            wipe_attempt += 1;
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        if wipe_attempt > 1 {
+            print_line(
+                &multi_progress,
+                format!(
+                    "slot {} wipe command sent on attempt {}",
+                    slot_label(self.slot),
+                    wipe_attempt
+                ),
+            );
         }
 
         let spinner = multi_progress.add(ProgressBar::new_spinner());
-        spinner.set_message(format!("Wiping old firmware on slot {}", self.slot));
+        spinner.set_message(format!(
+            "Wiping old firmware on slot {}",
+            slot_label(self.slot)
+        ));
         spinner.enable_steady_tick(Duration::from_millis(100));
         //wait for interrupt to happen or 2.5 secondes to pass, wiping the memory takes some time.
-        _ = timeout(Duration::from_millis(3500), interrupt).await;
+        let wipe_start = Instant::now();
+        //only the interrupt tells when the wipe is done, in timed mode the duration is unknown
+        let wipe_time = wait_interrupt(interrupt, Duration::from_millis(3500))
+            .await
+            .then(|| wipe_start.elapsed());
         spinner.finish_and_clear();
+        self.wipe_time = wipe_time;
+        //the wipe always ends in an interrupt, a module that let it time out is mis-wired or its interrupt is dead
+        if wipe_time.is_none() && self.interrupt.take().is_some() {
+            multi_progress.suspend(|| {
+                print_warning(format!(
+                    "Warning: slot {} interrupt never fired, falling back to timed mode",
+                    slot_label(self.slot)
+                ))
+            });
+        }
+        if let Some(wipe_time) = wipe_time {
+            print_line(
+                &multi_progress,
+                format!(
+                    "slot {} wiped in {} ms",
+                    slot_label(self.slot),
+                    wipe_time.as_millis()
+                ),
+            );
+        }
 
-        let progress = multi_progress.add(ProgressBar::new(lines.len() as u64));
-        progress.set_style(style);
+        //a step for every data record and a last one for the handshake that starts the firmware, the header and count
+        //records are sent too but don't move the bar, so it reaches the end exactly when the upload is done
+        let data_records = lines.iter().filter(|line| is_data_record(line)).count();
+        let progress = multi_progress.add(ProgressBar::new(data_records as u64 + 1));
+        progress.set_style(style.clone());
         progress.set_message(format!(
             "Uploading firmware {} to slot {}",
-            new_firmware.as_string(),
-            self.slot
+            new_firmware.version.as_string(),
+            slot_label(self.slot)
         ));
 
         let mut line_number: usize = 0;
@@ -587,8 +2265,32 @@ impl Module {
         let mut message_type: u8 = 0;
         let mut firmware_line_check: usize = usize::MAX; //set line check to usize::MAX for the first message so we know its the first message
         let mut firmware_error_counter: u8 = 0;
+        let mut retries: u32 = 0; //total amount of errors during this upload, firmware_error_counter resets on every successful line
+        let mut reopened = false; //the spidev is reopened at most once per upload
+        let mut health = UploadHealth::Clean;
 
         while message_type != 7 {
+            //the bar shows how close the current line is to giving up on the upload
+            let current_health = UploadHealth::of(firmware_error_counter);
+            if current_health != health {
+                health = current_health;
+                progress.set_style(match health {
+                    UploadHealth::Clean => style.clone(),
+                    UploadHealth::Struggling => progress_style_colored("yellow/blue"),
+                    UploadHealth::Failing => progress_style_colored("red/blue"),
+                });
+            }
+            //exiting the bootloader now would start a partial firmware stamped with the new version, so the module
+            //is marked as wiped instead, which also makes the next run offer to restore it
+            if cancel.load(Ordering::Relaxed) {
+                progress.set_style(failed_progress_style());
+                progress.abandon_with_message("Upload cancelled");
+                if options.stats {
+                    self.record_stats(retries, wipe_time, false);
+                }
+                self.wipe_module_error(permit).await;
+                return Err(UploadError::Cancelled(self.slot));
+            }
             // 7 marks the last line of the .srec file
             message_type = u8::from_str_radix(lines[line_number].get(1..2).unwrap(), 16).unwrap();
 
@@ -601,34 +2303,33 @@ impl Module {
                 tx_buf[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
                 tx_buf[2] = 49;
                 tx_buf[BOOTMESSAGE_LENGTH - 1] =
-                    calculate_checksum(&tx_buf, BOOTMESSAGE_LENGTH - 1);
-                let interrupt = self.interrupt.next();
-                match self
-                    .spidev
-                    .transfer(&mut SpidevTransfer::read_write(&tx_buf, &mut rx_buf))
-                {
+                    self.checksum.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+                let interrupt = self.interrupt.as_mut().map(|interrupt| interrupt.next());
+                match self.spidev.transfer(&tx_buf, &mut rx_buf) {
                     Ok(()) => {
-                        if rx_buf[BOOTMESSAGE_LENGTH - 1]
-                            == calculate_checksum(&rx_buf, BOOTMESSAGE_LENGTH - 1)
-                            && firmware_line_check
-                                == u16::from_be_bytes(clone_into_array(rx_buf.get(6..8).unwrap()))
-                                    as usize
-                            && rx_buf[8] == 1
+                        if LineResponse::of(
+                            &rx_buf,
+                            self.checksum,
+                            firmware_line_check,
+                            line_number,
+                        ) == LineResponse::Ack
                         {
-                            _ = timeout(Duration::from_millis(5), interrupt).await;
+                            wait_interrupt(interrupt, Duration::from_millis(5)).await;
                         } else {
                             firmware_error_counter += 1;
+                            retries += 1;
                             mem::swap(&mut line_number, &mut firmware_line_check);
                             message_type = 0; //last message failed, set the message type to not 7 again so we don't exit the while loop
-                            _ = timeout(Duration::from_millis(5), interrupt).await;
+                            wait_interrupt(interrupt, Duration::from_millis(5)).await;
                             continue;
                         }
                     }
                     Err(_) => {
                         firmware_error_counter += 1;
+                        retries += 1;
                         mem::swap(&mut line_number, &mut firmware_line_check);
                         message_type = 0; //last message failed, set the message type to not 7 again so we don't exit the while loop
-                        _ = timeout(Duration::from_millis(5), interrupt).await;
+                        wait_interrupt(interrupt, Duration::from_millis(5)).await;
                         continue;
                     }
                 }
@@ -666,28 +2367,64 @@ impl Module {
             )
             .unwrap();
 
-            tx_buf[BOOTMESSAGE_LENGTH - 1] = calculate_checksum(&tx_buf, BOOTMESSAGE_LENGTH - 1);
-            let interrupt = self.interrupt.next();
-            match self
-                .spidev
-                .transfer(&mut SpidevTransfer::read_write(&tx_buf, &mut rx_buf))
-            {
+            tx_buf[BOOTMESSAGE_LENGTH - 1] =
+                self.checksum.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+            let interrupt = self.interrupt.as_mut().map(|interrupt| interrupt.next());
+            match self.spidev.transfer(&tx_buf, &mut rx_buf) {
                 Ok(_) => {
-                    // the first message will always receive junk, ignore this junk and continue to line 1
+                    // the first message will always receive junk, ignore this junk and continue to the next line, the same goes for the first message after a resync
                     if firmware_line_check == usize::MAX {
+                        firmware_line_check = line_number;
                         line_number += 1;
-                        firmware_line_check = 0; // no ; to exit the match statement
-                        _ = timeout(Duration::from_micros(1000), interrupt).await;
+                        wait_interrupt(interrupt, Duration::from_micros(1000)).await;
+                        continue;
+                    }
+                    let response =
+                        LineResponse::of(&rx_buf, self.checksum, firmware_line_check, line_number);
+
+                    //swapping to the other line assumes the module is one line behind, which it isn't when it answers
+                    //for a line that was never in flight, so start over from the oldest unconfirmed line instead
+                    //the last line is left to the regular retries, it can't be sent as the first message
+                    let resume = line_number.min(firmware_line_check);
+                    if let (LineResponse::Desync(received_line), true) =
+                        (response, resume < lines.len() - 1)
+                    {
+                        firmware_error_counter += 1;
+                        retries += 1;
+                        //an odd counter makes the next ack swap the lines back, the resync doesn't swap them
+                        firmware_error_counter += firmware_error_counter & 0b1;
+                        wait_interrupt(interrupt, Duration::from_millis(5)).await;
+                        let reported = self
+                            .request_status()
+                            .map(|line| format!(", the module reports line {}", line))
+                            .unwrap_or_default();
+                        progress.println(format!(
+                            "Error slot {}: module answered for line {} while expecting line {} or {}{}, resyncing from line {}",
+                            slot_label(self.slot),
+                            received_line,
+                            firmware_line_check,
+                            line_number,
+                            reported,
+                            resume
+                        ));
+                        if firmware_error_counter > 10 {
+                            progress.set_style(failed_progress_style());
+                            progress.abandon_with_message(
+                                "Error: upload failed, the module kept losing track of the firmware lines",
+                            );
+                            if options.stats {
+                                self.record_stats(retries, wipe_time, false);
+                            }
+                            return Err(UploadError::FirmwareCorrupted(self.slot));
+                        }
+                        line_number = resume;
+                        firmware_line_check = usize::MAX;
+                        message_type = 0;
                         continue;
                     }
-                    let received_line =
-                        u16::from_be_bytes(clone_into_array(rx_buf.get(6..8).unwrap()));
-                    let local_checksum_match = rx_buf[BOOTMESSAGE_LENGTH - 1]
-                        == calculate_checksum(&rx_buf, BOOTMESSAGE_LENGTH - 1);
-                    let remote_checksum_match = rx_buf[8] == 1;
-                    let received_line_match = received_line as usize == firmware_line_check;
-
-                    if local_checksum_match && received_line_match && remote_checksum_match {
+
+                    if response == LineResponse::Ack {
+                        let acked = firmware_line_check;
                         if firmware_error_counter & 0b1 > 0 {
                             // if the error counter is uneven swap line number and the line being checked
                             std::mem::swap(&mut line_number, &mut firmware_line_check);
@@ -701,19 +2438,24 @@ impl Module {
                             tx_buf_escape[0] = 49;
                             tx_buf_escape[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
                             tx_buf_escape[2] = 49;
-                            tx_buf_escape[BOOTMESSAGE_LENGTH - 1] =
-                                calculate_checksum(&tx_buf_escape, BOOTMESSAGE_LENGTH - 1);
+                            tx_buf_escape[BOOTMESSAGE_LENGTH - 1] = self
+                                .checksum
+                                .calculate(&tx_buf_escape, BOOTMESSAGE_LENGTH - 1);
                             time::sleep(Duration::from_millis(5)).await;
-                            _ = self.spidev.transfer(&mut SpidevTransfer::read_write(
-                                &tx_buf_escape,
-                                &mut rx_buf_escape,
-                            ));
-                            if rx_buf_escape[rx_buf_escape[1] as usize]
-                                == calculate_checksum(&rx_buf_escape, rx_buf_escape[1] as usize)
-                                && rx_buf_escape[6] == 20
+                            let transferred = self
+                                .spidev
+                                .transfer(&tx_buf_escape, &mut rx_buf_escape)
+                                .is_ok();
+                            if transferred
+                                && BootResponse::parse_sized(&rx_buf_escape, self.checksum)
+                                    .is_some_and(|response| response.payload.first() == Some(&20))
                             {
-                                // received response from bootloader, finish the last line of the progress bar and let the while loop exit.
-                                progress.inc(1);
+                                // received response from bootloader, every record made it, including the last data record this
+                                // response acked, so fill the bar and let the while loop exit.
+                                progress.set_position(progress.length().unwrap_or_default());
+                                if options.agent {
+                                    remote::report_progress(self.slot, &progress);
+                                }
                             } else {
                                 // last message failed, set the message type to not 7 again so we don't exit the while loop and try again instead
                                 message_type = 0;
@@ -722,120 +2464,264 @@ impl Module {
                             // normal firmware message succes
                             line_number += 1;
                             firmware_error_counter = 0;
-                            progress.inc(1);
+                            if is_data_record(lines[acked]) {
+                                progress.inc(1);
+                                if options.agent {
+                                    remote::report_progress(self.slot, &progress);
+                                }
+                            }
                         }
                     } else {
                         mem::swap(&mut line_number, &mut firmware_line_check);
                         message_type = 0;
                         firmware_error_counter += 1;
+                        retries += 1;
 
-                        #[cfg(debug_assertions)]
-                        {
+                        if options.verbose || cfg!(debug_assertions) {
                             progress.println(format!(
                                 "error number {}, rx: {:?}",
                                 firmware_error_counter, rx_buf
                             ));
-                            if !local_checksum_match {
-                                progress.println(format!(
-									"Error slot {}: checksum from module: {} didn't match with the calculated one: {}",
-									self.slot, rx_buf[BOOTMESSAGE_LENGTH-1], calculate_checksum(&rx_buf, BOOTMESSAGE_LENGTH-1)
-								));
-                            }
-
-                            if !received_line_match {
-                                // use line number as it has been mem::swapped just before with firmware line check, which is the on we want
-                                progress.println(format!("Error slot {}: firmware line: {} didn't match with the reply from the module: {}",self.slot, line_number, received_line));
-                            }
-
-                            if !remote_checksum_match {
-                                progress.println(format!(
-									"Error slot {}: module did not receive the firmware line correctly",
-									self.slot
-								));
-                            }
+                            // use line number as it has been mem::swapped just before with firmware line check, which is the one we want
+                            progress.println(match response {
+                                LineResponse::NoResponse => format!(
+                                    "Error slot {}: no response from the module to firmware line {}",
+                                    slot_label(self.slot), line_number
+                                ),
+                                LineResponse::Corrupt => format!(
+                                    "Error slot {}: checksum from module: {} didn't match with the calculated one: {}",
+                                    slot_label(self.slot), rx_buf[BOOTMESSAGE_LENGTH - 1], self.checksum.calculate(&rx_buf, BOOTMESSAGE_LENGTH - 1)
+                                ),
+                                LineResponse::WrongLine(received_line)
+                                | LineResponse::Desync(received_line) => format!(
+                                    "Error slot {}: firmware line: {} didn't match with the reply from the module: {}",
+                                    slot_label(self.slot), line_number, received_line
+                                ),
+                                LineResponse::Nak | LineResponse::Ack => format!(
+                                    "Error slot {}: module did not receive firmware line {} correctly (NAK)",
+                                    slot_label(self.slot), line_number
+                                ),
+                            });
                         }
                         if firmware_error_counter > 10 {
-                            if !local_checksum_match {
-                                progress.abandon_with_message(
-                                    "Error: upload failed, checksum didn't match",
-                                );
-                            } else if !received_line_match {
-                                progress.abandon_with_message("Error: upload failed, firmware line didn't match with the reply from the module");
-                            } else if !remote_checksum_match {
-                                progress.abandon_with_message("Error: upload failed, module did not receive the firmware line correctly");
-                            } else {
-                                progress
-                                    .abandon_with_message("Error: upload failed, no idea how\n");
+                            progress.set_style(failed_progress_style());
+                            progress.abandon_with_message(match response {
+                                LineResponse::NoResponse => "Error: upload failed, the module stopped responding",
+                                LineResponse::Corrupt => "Error: upload failed, checksum didn't match",
+                                LineResponse::WrongLine(_) | LineResponse::Desync(_) => "Error: upload failed, firmware line didn't match with the reply from the module",
+                                LineResponse::Nak | LineResponse::Ack => "Error: upload failed, module did not receive the firmware line correctly",
+                            });
+                            if options.stats {
+                                self.record_stats(retries, wipe_time, false);
                             }
                             return Err(UploadError::FirmwareCorrupted(self.slot));
                         }
                     }
                 }
-                Err(_) => {
+                Err(err) => {
                     mem::swap(&mut line_number, &mut firmware_line_check);
                     message_type = 0;
                     firmware_error_counter += 1;
+                    retries += 1;
                     progress.println(format!(
                         "Error slot {}: failed to transfer spi message",
-                        self.slot
+                        slot_label(self.slot)
                     ));
+                    //retrying a device that is gone only burns the retries, try to get it back once instead
+                    if transport::device_gone(&err) {
+                        if reopened {
+                            progress.set_style(failed_progress_style());
+                            progress.abandon_with_message(format!(
+                                "Error: upload failed, {} went away again",
+                                self.spidev.path()
+                            ));
+                            if options.stats {
+                                self.record_stats(retries, wipe_time, false);
+                            }
+                            return Err(UploadError::FirmwareCorrupted(self.slot));
+                        }
+                        reopened = true;
+                        if let Err(err) = self.spidev.reopen() {
+                            progress.set_style(failed_progress_style());
+                            progress.abandon_with_message(format!(
+                                "Error: upload failed, {} went away and could not be reopened: {}",
+                                self.spidev.path(),
+                                err
+                            ));
+                            if options.stats {
+                                self.record_stats(retries, wipe_time, false);
+                            }
+                            return Err(UploadError::FirmwareCorrupted(self.slot));
+                        }
+                        progress.println(format!(
+                            "slot {}: reopened {}",
+                            slot_label(self.slot),
+                            self.spidev.path()
+                        ));
+                    }
                     if firmware_error_counter > 10 {
+                        progress.set_style(failed_progress_style());
                         progress.abandon_with_message("Error: upload failed, spi transfer failed");
+                        if options.stats {
+                            self.record_stats(retries, wipe_time, false);
+                        }
                         return Err(UploadError::FirmwareCorrupted(self.slot));
                     }
                 }
             } //exit match
               //wait for interrupt to happen (or 1 millisecond to pass), then continue with the next line
-            _ = timeout(Duration::from_micros(1000), interrupt).await;
+            wait_interrupt(interrupt, Duration::from_micros(1000)).await;
         } //exit while
         progress.finish_with_message("Upload successfull!");
         self.cancel_firmware_upload(&mut tx_buf);
+        if options.stats {
+            self.record_stats(retries, wipe_time, true);
+        }
+        if options.count_flashes {
+            self.count_flash();
+        }
         Ok(())
     }
 
+    /// add one to the flash count of this module in the flash counts file, keyed by front QR code \
+    /// the file is replaced in one rename, so an exit halfway never loses the counts of the other modules
+    fn count_flash(&self) {
+        let _counting = FLASH_COUNTS_LOCK
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut counts = read_flash_counts();
+        *counts.entry(self.qr_front).or_default() += 1;
+        let contents: String = counts
+            .iter()
+            .map(|(qr, count)| format!("{}:{}\n", qr, count))
+            .collect();
+        let temporary = format!("{}.tmp", FLASH_COUNTS_FILE);
+        if fs::create_dir_all(MODULES_DIR)
+            .and_then(|()| fs::write(&temporary, contents))
+            .and_then(|()| fs::rename(&temporary, FLASH_COUNTS_FILE))
+            .is_err()
+        {
+            print_warning(format!(
+                "Could not save the flash count to {}",
+                FLASH_COUNTS_FILE
+            ));
+        }
+    }
+
+    /// append the amount of retries and the wipe time of an upload to the stats file, keyed by slot and front QR code
+    fn record_stats(&self, retries: u32, wipe_time: Option<Duration>, succeeded: bool) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or(0);
+        let record = format!(
+            "{}:{}:{}:{}:{}:{}\n",
+            timestamp,
+            self.slot,
+            self.qr_front,
+            retries,
+            if succeeded { "ok" } else { "failed" },
+            wipe_time
+                .map(|wipe_time| wipe_time.as_millis().to_string())
+                .unwrap_or_default()
+        );
+        if fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(STATS_FILE)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, record.as_bytes()))
+            .is_err()
+        {
+            print_warning(format!(
+                "Could not save upload statistics to {}",
+                STATS_FILE
+            ));
+        }
+    }
+
     /// Update a module, checking for new matching firmwares in the firmwares parameter \
     /// The outer Result<Result, UploadError> indicates whether there was an error in the upload process \
-    /// The inner Result<Module,Module> indicates whether there was an available update or not.
+    /// The inner Result<(Module, location), Module> indicates whether there was an available update or not, the
+    /// location is the file the update was flashed from.
     async fn update_module(
         mut self,
-        firmwares: &[FirmwareVersion],
+        permit: &WipePermit,
+        firmwares: &[FirmwareFile],
         multi_progress: MultiProgress,
         style: ProgressStyle,
-    ) -> Result<Result<Self, Self>, UploadError> {
+        options: &Options,
+        cancel: &AtomicBool,
+    ) -> Result<Result<(Self, String), Self>, UploadError> {
+        if options.is_pinned(self.slot) {
+            println!("slot {} is pinned, skipped", slot_label(self.slot));
+            return Ok(Err(self));
+        }
+        //its hardware bytes are zero too, so no staged firmware can be matched to it, unlike a wiped module
+        if self.firmware.is_factory_blank() {
+            print_warning(format!(
+                "slot {} is unprogrammed (factory blank), it needs an initial flash with overwrite as its hardware type is unknown",
+                slot_label(self.slot)
+            ));
+            return Ok(Err(self));
+        }
+        //a wiped module is always older, it has no usable software at all
+        if let Some(older_than) = options.older_than {
+            let software = self.firmware.get_software_version();
+            if !software.is_blank() && software >= older_than {
+                println!(
+                    "skipping slot {}, its software {} is not older than {}",
+                    slot_label(self.slot),
+                    software,
+                    older_than
+                );
+                return Ok(Err(self));
+            }
+        }
         if let Some((index, _junk)) = firmwares
             .iter()
             .enumerate()
+            .map(|(i, available)| (i, available.version))
             .filter(|(_i, available)| available.get_hardware() == self.firmware.get_hardware()) //filter out incorrect hardware versions
             .filter(|(_i, available)| {
-                (available.get_software() > self.firmware.get_software()
-                    || self.firmware.get_software() == [255u8, 255, 255])
-                    && available.get_software() != [255u8, 255, 255]
+                (available.get_software_version() > self.firmware.get_software_version()
+                    || self.firmware.get_software_version().is_blank())
+                    //a staged 0.0.0 is a real version, only the wiped marker can't be flashed
+                    && available.get_software_version() != SoftwareVersion::BLANK
             }) //filter out wrong software versions
-            .map(|(i, available)| (i, available.get_software())) //turn them all into software versions
+            .map(|(i, available)| (i, available.get_software_version())) //turn them all into software versions
             .reduce(|acc, (i, software)| if acc.1 < software { (i, software) } else { acc })
         //cant use min/max because of the tuple, have to manually compare it in a reduce function
         {
+            //duplicates and symlinks can make the version alone ambiguous, so the file is named too
             println!(
-                "updating slot {} from {} to {}",
-                self.slot,
+                "updating slot {} from {} to {} with {}",
+                slot_label(self.slot),
                 self.firmware.as_string(),
-                firmwares.get(index).unwrap().as_string()
+                firmwares.get(index).unwrap().version.as_string(),
+                firmwares.get(index).unwrap().location()
             );
             match self
-                .overwrite_module(firmwares.get(index).unwrap(), multi_progress, style)
+                .overwrite_module_retrying(
+                    permit,
+                    firmwares.get(index).unwrap(),
+                    multi_progress,
+                    style,
+                    options,
+                    cancel,
+                )
                 .await
             {
                 Ok(()) => {
-                    self.firmware = *firmwares.get(index).unwrap();
-                    Ok(Ok(self)) //firmware updated successfully
+                    self.firmware = firmwares.get(index).unwrap().version;
+                    Ok(Ok((self, firmwares.get(index).unwrap().location()))) //firmware updated successfully
                 }
                 Err(err) => {
                     if let UploadError::FirmwareCorrupted(slot) = err {
-                        eprintln!(
+                        print_error(format!(
                             "firmware upload critically failed on slot {}, wiping firmware...",
-                            slot
-                        );
-                        self.wipe_module_error().await;
+                            slot_label(slot)
+                        ));
+                        self.wipe_module_error(permit).await;
                     }
                     Err(err)
                 } //error uploading the new firmware
@@ -846,76 +2732,765 @@ impl Module {
         }
     }
 
+    /// find the highest spi clock speed at which BENCHMARK_TRIALS info requests all get a valid response,
+    /// stopping at the first speed that has errors. The module is left running its firmware at spi_speed().
+    async fn benchmark(&mut self) -> Option<u32> {
+        let mut tx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+        let mut rx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+        tx_buf[0] = 9;
+        tx_buf[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
+        tx_buf[2] = 9;
+        tx_buf[BOOTMESSAGE_LENGTH - 1] = self.checksum.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+
+        let mut reliable = None;
+        for speed in BENCHMARK_SPEEDS {
+            if let Err(err) = self.spidev.configure(speed) {
+                print_error(format!(
+                    "Error: could not set the spi speed to {} Hz: {}",
+                    speed, err
+                ));
+                break;
+            }
+            let mut errors = 0;
+            for _ in 0..BENCHMARK_TRIALS {
+                let interrupt = self.interrupt.as_mut().map(|interrupt| interrupt.next());
+                let valid = self.spidev.transfer(&tx_buf, &mut rx_buf).is_ok()
+                    && BootResponse::parse(&rx_buf, self.checksum)
+                        .is_some_and(|response| response.answers(9));
+                if !valid {
+                    errors += 1;
+                }
+                wait_interrupt(interrupt, Duration::from_millis(1)).await;
+            }
+            println!(
+                "slot {} at {} Hz: {} errors in {} reads",
+                slot_label(self.slot),
+                speed,
+                errors,
+                BENCHMARK_TRIALS
+            );
+            if errors > 0 {
+                break;
+            }
+            reliable = Some(speed);
+        }
+        _ = self.spidev.configure(spi_speed());
+        self.cancel_firmware_upload(&mut tx_buf);
+        reliable
+    }
+
+    /// ask the module for its status with a dummy message, returns the line number it last answered for
+    fn request_status(&mut self) -> Option<u16> {
+        let mut tx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+        let mut rx_buf = [0u8; BOOTMESSAGE_LENGTH + 1];
+        tx_buf[0] = 49;
+        tx_buf[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
+        tx_buf[2] = 49;
+        tx_buf[BOOTMESSAGE_LENGTH - 1] = self.checksum.calculate(&tx_buf, BOOTMESSAGE_LENGTH - 1);
+        self.spidev.transfer(&tx_buf, &mut rx_buf).ok()?;
+        BootResponse::parse(&rx_buf, self.checksum).map(|response| response.line)
+    }
+
     /// Cancel the firmware upload of the module bringing the module into operational state
     fn cancel_firmware_upload(&mut self, tx_buf: &mut [u8]) {
         tx_buf[0] = 19;
         tx_buf[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
         tx_buf[2] = 19;
-        tx_buf[BOOTMESSAGE_LENGTH - 1] = calculate_checksum(tx_buf, BOOTMESSAGE_LENGTH - 1);
-        _ = self.spidev.transfer(&mut SpidevTransfer::write(tx_buf));
+        tx_buf[BOOTMESSAGE_LENGTH - 1] = self.checksum.calculate(tx_buf, BOOTMESSAGE_LENGTH - 1);
+        _ = self.spidev.write(tx_buf);
     }
 }
 
 impl Display for Module {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.firmware.is_factory_blank() {
+            return write!(
+                f,
+                "slot {}: unprogrammed (factory blank), needs an initial flash with overwrite",
+                slot_label(self.slot)
+            );
+        }
         let hardware = self.firmware.get_hardware();
-        let software = self.firmware.get_software();
-        write!(
-            f,
-            "{}",
-            match hardware[1] {
-                10 => match hardware[2] {
-                    1 => format!(
-                        "slot {}: 6 Channel Input module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    2 => format!(
-                        "slot {}: 10 Channel Input module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    3 => format!(
-                        "slot {}: 4-20mA Input module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    _ => format!("slot {}: unknown: {}", self.slot, self.firmware.as_string()),
-                },
-                20 => match hardware[2] {
-                    1 => format!(
-                        "slot {}: 2 Channel Output module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    2 => format!(
-                        "slot {}: 6 Channel Output module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    3 => format!(
-                        "slot {}: 10 Channel Output module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    _ => format!("slot {}: unknown: {}", self.slot, self.firmware.as_string()),
-                },
-                30 => match hardware[2] {
-                    3 => format!(
-                        "slot {}: ANLEG IR module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    _ => format!("slot {}: unknown: {}", self.slot, self.firmware.as_string()),
-                },
-                40 => match hardware[2] {
-                    1 => format!(
-                        "slot {}: ANLEG RTC Control module version {} sw: {}.{}.{}",
-                        self.slot, hardware[3], software[0], software[1], software[2]
-                    ),
-                    _ => format!("slot {}: unknown: {}", self.slot, self.firmware.as_string()),
+        match self.firmware.module_type() {
+            Some(name) => write!(
+                f,
+                "slot {}: {} module version {} sw: {}",
+                slot_label(self.slot),
+                name,
+                hardware[3],
+                self.firmware.get_software_version()
+            ),
+            None => write!(
+                f,
+                "slot {}: unknown: {}",
+                slot_label(self.slot),
+                self.firmware.as_string()
+            ),
+        }
+    }
+}
+
+/// check that the filesystem of the modules file has enough room left to save the result of a flash
+fn check_free_space(min_free_space: u64) -> Result<(), String> {
+    let stat = statvfs(MODULES_DIR)
+        .or_else(|_| statvfs("/usr/lib/"))
+        .map_err(|err| {
+            format!(
+                "Could not determine the free space for {}: {}",
+                MODULES_DIR, err
+            )
+        })?;
+    let free = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+    if free < min_free_space {
+        return Err(format!(
+            "Only {} bytes free for {}, the new module state might not be saved after flashing",
+            free,
+            modules_file_path()
+        ));
+    }
+    Ok(())
+}
+
+/// print the version and the supported commands and options as json
+fn print_capabilities() {
+    let to_json_list = |list: &[&str]| {
+        list.iter()
+            .map(|item| format!("\"{}\"", item))
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+    println!(
+        "{{\"version\":\"{}\",\"commands\":[{}],\"options\":[{}]}}",
+        VERSION,
+        to_json_list(COMMANDS),
+        to_json_list(OPTIONS)
+    );
+}
+
+/// quote a string for json, control characters like the newlines in io errors are escaped too
+fn json_string(string: &str) -> String {
+    let mut quoted = String::with_capacity(string.len() + 2);
+    quoted.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// look up the name of a manufacturer id in MANUFACTURERS
+fn known_manufacturer(id: u32) -> Option<&'static str> {
+    MANUFACTURERS
+        .iter()
+        .find(|(known, _)| *known == id)
+        .map(|(_, name)| *name)
+}
+
+/// the modules in a modules file as json, empty slots are left out \
+/// the modules flashed during the run are added as flashed when there are any, to trace which file went where \
+/// modules that were just scanned add their extra info bytes by slot, which the modules file doesn't keep \
+/// the wall time of an update all is added when there is one
+fn modules_file_json(
+    contents: &str,
+    flashed: &[String],
+    extra_info: &[(u8, String)],
+    qr_side: QrSide,
+    flash_time: Option<Duration>,
+) -> String {
+    let lines: Vec<Vec<&str>> = contents
+        .split('\n')
+        .map(|line| line.split(':').collect())
+        .collect();
+    let field = |line: usize, slot: usize| {
+        lines
+            .get(line)
+            .and_then(|values| values.get(slot))
+            .copied()
+            .unwrap_or_default()
+    };
+    let number = |line: usize, slot: usize| field(line, slot).parse::<u32>().unwrap_or(0);
+    let modules: Vec<String> = (0..lines.first().map(Vec::len).unwrap_or(0))
+        .filter(|slot| !field(0, *slot).is_empty())
+        .map(|slot| {
+            let extra_info = extra_info
+                .iter()
+                .find(|(extra_slot, _)| *extra_slot as usize == slot + 1)
+                .map(|(_, hex)| format!(",\"extra_info\":{}", json_string(hex)))
+                .unwrap_or_default();
+            let qrs: String = qr_side
+                .columns([("qr_front", number(2, slot)), ("qr_back", number(3, slot))])
+                .map(|(key, qr)| format!(",\"{}\":{}", key, qr))
+                .collect();
+            format!(
+                "{{\"slot\":{},\"label\":{},\"firmware\":{},\"manufacturer\":{},\"manufacturer_name\":{}{}{}}}",
+                slot + 1,
+                json_string(&slot_label(slot as u8 + 1)),
+                json_string(field(0, slot)),
+                number(1, slot),
+                known_manufacturer(number(1, slot)).map_or("null".to_string(), json_string),
+                qrs,
+                extra_info
+            )
+        })
+        .collect();
+    let flashed = if flashed.is_empty() {
+        String::new()
+    } else {
+        format!(",\"flashed\":[{}]", flashed.join(","))
+    };
+    let flash_time = flash_time
+        .map(|flash_time| format!(",\"flash_time_ms\":{}", flash_time.as_millis()))
+        .unwrap_or_default();
+    format!(
+        "{{\"version\":\"{}\",\"modules\":[{}]{}{}}}",
+        VERSION,
+        modules.join(","),
+        flashed,
+        flash_time
+    )
+}
+
+/// the extra info bytes by slot of the modules that have any, for modules_file_json
+fn extra_info(modules: &[Module]) -> Vec<(u8, String)> {
+    modules
+        .iter()
+        .filter_map(|module| Some((module.slot, module.extra_info_hex()?)))
+        .collect()
+}
+
+/// append the modules file as json with the current time to the gzip compressed history file, one json line per scan
+fn append_history(path: &str, modules_file: &str, extra_info: &[(u8, String)]) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let snapshot = format!(
+        "{{\"timestamp\":{},\"scan\":{}}}\n",
+        timestamp,
+        modules_file_json(modules_file, &[], extra_info, QrSide::Both, None)
+    );
+    if let Err(err) = gzip::append(path, snapshot.as_bytes()) {
+        print_warning(format!(
+            "Warning: could not append the scan to {}: {}",
+            path, err
+        ));
+    }
+}
+
+/// remember that a module was flashed with a file, for the report sent at the end of the run \
+/// the wipe time is null when timed mode couldn't measure it
+fn record_flashed(slot: u8, firmware: &FirmwareFile, wipe_time: Option<Duration>) {
+    FLASHED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(format!(
+            "{{\"slot\":{},\"label\":{},\"firmware\":{},\"file\":{},\"wipe_ms\":{}}}",
+            slot,
+            json_string(&slot_label(slot)),
+            json_string(&firmware.version.as_string()),
+            json_string(&firmware.location()),
+            wipe_time.map_or("null".to_string(), |wipe_time| wipe_time
+                .as_millis()
+                .to_string())
+        ));
+}
+
+/// the wipe time of a module for the summary lines
+fn describe_wipe(wipe_time: Option<Duration>) -> String {
+    wipe_time.map_or("wipe time unknown in timed mode".to_string(), |wipe_time| {
+        format!("wiped in {} ms", wipe_time.as_millis())
+    })
+}
+
+/// print the modules as a table with aligned columns, padded to the widest value of every column
+fn print_module_table(modules: &[Module], qr_side: QrSide) {
+    let mut header = vec!["slot", "type", "hardware", "software", "manufacturer"];
+    header.extend(qr_side.columns(["front QR", "rear QR"]));
+    let rows: Vec<Vec<String>> = modules
+        .iter()
+        .map(|module| {
+            let hardware = module.firmware.get_hardware();
+            let mut row = vec![
+                slot_label(module.slot),
+                if module.firmware.is_factory_blank() {
+                    "unprogrammed".to_string()
+                } else {
+                    module
+                        .firmware
+                        .module_type()
+                        .unwrap_or("unknown")
+                        .to_string()
                 },
-                _ => format!("slot {}: unknown: {}", self.slot, self.firmware.as_string()),
+                format!(
+                    "{}-{}-{}-{}",
+                    hardware[0], hardware[1], hardware[2], hardware[3]
+                ),
+                module.firmware.get_software_version().to_string(),
+                module
+                    .manufacturer_name()
+                    .map_or_else(|| module.manufacturer.to_string(), str::to_string),
+            ];
+            row.extend(qr_side.columns([module.qr_front, module.qr_back].map(|qr| qr.to_string())));
+            row
+        })
+        .collect();
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+    let print_row = |row: &[&str]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:<width$}", value, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    };
+    print_row(&header);
+    for row in &rows {
+        print_row(&row.iter().map(String::as_str).collect::<Vec<&str>>());
+    }
+}
+
+/// the state of every slot of the controller on one line like `IV: [1:6chIn/5.0.9] [2:empty] [3:2chOut/wiped]`
+fn scan_oneline(modules: &[Module], controller: &ControllerTypes) -> String {
+    let slots: Vec<String> = (1..=controller.slot_count())
+        .map(|slot| {
+            let state = match modules.iter().find(|module| module.slot == slot) {
+                None => "empty".to_string(),
+                Some(module) if module.firmware.is_factory_blank() => "unprogrammed".to_string(),
+                Some(module) => {
+                    let kind = module.firmware.module_type_short().map_or_else(
+                        || {
+                            let hardware = module.firmware.get_hardware();
+                            format!(
+                                "{}-{}-{}-{}",
+                                hardware[0], hardware[1], hardware[2], hardware[3]
+                            )
+                        },
+                        str::to_string,
+                    );
+                    let software = module.firmware.get_software_version();
+                    if software.is_blank() {
+                        format!("{}/wiped", kind)
+                    } else {
+                        format!("{}/{}", kind, software)
+                    }
+                }
+            };
+            format!("[{}:{}]", slot_label(slot), state)
+        })
+        .collect();
+    format!("{}: {}", controller.short_name(), slots.join(" "))
+}
+
+/// warn about the slots that answered the scan with a single repeated byte, which is a wiring fault rather than an
+/// empty slot when a module is fitted \
+/// an empty slot reads the same, so only the slots that held a module in the previous modules file are reported,
+/// or every one of them when verbose
+fn print_stuck_slots(previous_modules_file: &str, verbose: bool) {
+    let recorded: Vec<&str> = previous_modules_file
+        .split('\n')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .collect();
+    let stuck = STUCK_SLOTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for (slot, byte) in stuck.iter() {
+        let had_module = recorded
+            .get(*slot as usize - 1)
+            .is_some_and(|firmware| !firmware.is_empty());
+        if !had_module && !verbose {
+            continue;
+        }
+        print_warning(format!(
+            "Warning: slot {} answered only {:#04x}, MISO appears stuck {}, check the connection if a module is fitted",
+            slot_label(*slot),
+            byte,
+            if *byte == 0 { "low" } else { "high" }
+        ));
+    }
+}
+
+/// the slots in PROGRESS_FILE, none if there is no file
+fn load_update_progress() -> Vec<u8> {
+    fs::read_to_string(PROGRESS_FILE)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect()
+}
+
+/// replace PROGRESS_FILE with the finished slots, renamed into place so an interruption never leaves half a file
+fn save_update_progress(slots: &[u8]) {
+    let contents: String = slots.iter().map(|slot| format!("{}\n", slot)).collect();
+    let temporary = format!("{}.tmp", PROGRESS_FILE);
+    if fs::write(&temporary, contents)
+        .and_then(|()| fs::rename(&temporary, PROGRESS_FILE))
+        .is_err()
+    {
+        print_warning(format!(
+            "Warning: could not record the update progress in {}, --resume won't skip the finished slots",
+            PROGRESS_FILE
+        ));
+    }
+}
+
+/// the flash counts recorded with --count-flashes by front QR code, lines that don't parse are skipped
+fn read_flash_counts() -> BTreeMap<u32, u32> {
+    fs::read_to_string(FLASH_COUNTS_FILE)
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let (qr, count) = line.split_once(':')?;
+            Some((qr.parse().ok()?, count.parse().ok()?))
+        })
+        .collect()
+}
+
+/// print how many times the module with this front QR code was flashed with --count-flashes
+fn print_flash_count(qr: &str) {
+    let qr: u32 = qr.parse().unwrap_or_else(|_| {
+        err_n_die(format!("{} is not a valid QR code", qr).as_str());
+    });
+    let count = read_flash_counts().get(&qr).copied().unwrap_or(0);
+    println!(
+        "Module {} was flashed {} time{}",
+        qr,
+        count,
+        if count == 1 { "" } else { "s" }
+    );
+}
+
+/// print the upload retry statistics recorded with --stats per slot and module
+fn print_stats() {
+    let contents = fs::read_to_string(STATS_FILE).unwrap_or_else(|_| {
+        err_n_die(format!("No statistics recorded yet in {}", STATS_FILE).as_str())
+    });
+    //(slot, qr) -> (retries, succeeded) in the order they were recorded, and the measured wipe times in ms
+    let mut records: BTreeMap<(u8, u32), Vec<(u32, bool)>> = BTreeMap::new();
+    let mut wipe_times: BTreeMap<(u8, u32), Vec<u32>> = BTreeMap::new();
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        //records from before the wipe time was recorded have 5 fields
+        let (fields, wipe_time) = match fields.as_slice() {
+            [fields @ .., wipe_time] if fields.len() == 5 => {
+                (fields, wipe_time.parse::<u32>().ok())
             }
-        )
+            fields => (fields, None),
+        };
+        if let [_timestamp, slot, qr, retries, result] = fields {
+            if let (Ok(slot), Ok(qr), Ok(retries)) = (
+                slot.parse::<u8>(),
+                qr.parse::<u32>(),
+                retries.parse::<u32>(),
+            ) {
+                records
+                    .entry((slot, qr))
+                    .or_default()
+                    .push((retries, *result == "ok"));
+                if let Some(wipe_time) = wipe_time {
+                    wipe_times.entry((slot, qr)).or_default().push(wipe_time);
+                }
+            }
+        }
+    }
+    if records.is_empty() {
+        err_n_die(format!("No valid statistics found in {}", STATS_FILE).as_str());
+    }
+    for ((slot, qr), uploads) in records {
+        let retries: Vec<u32> = uploads.iter().map(|(retries, _)| *retries).collect();
+        let failed = uploads.iter().filter(|(_, succeeded)| !succeeded).count();
+        let average = |retries: &[u32]| retries.iter().sum::<u32>() as f64 / retries.len() as f64;
+        //compare the newer half of the uploads with the older half
+        let trend = if retries.len() < 2 {
+            "not enough data"
+        } else {
+            let (older, newer) = retries.split_at(retries.len() / 2);
+            if average(newer) > average(older) {
+                "rising"
+            } else if average(newer) < average(older) {
+                "falling"
+            } else {
+                "stable"
+            }
+        };
+        let wipe = match wipe_times.get(&(slot, qr)) {
+            Some(times) => format!(
+                ", wipe {}-{} ms",
+                times.iter().min().unwrap(),
+                times.iter().max().unwrap()
+            ),
+            None => String::new(),
+        };
+        println!(
+            "slot {} (QR {}): {} uploads, {} failed, {:.1} retries on average, last: {}, trend: {}{}",
+            slot,
+            qr,
+            uploads.len(),
+            failed,
+            average(&retries),
+            retries
+                .iter()
+                .rev()
+                .take(5)
+                .rev()
+                .map(|retries| retries.to_string())
+                .collect::<Vec<String>>()
+                .join(" "),
+            trend,
+            wipe
+        );
+    }
+}
+
+/// print a line above the progress bars, or straight to stdout when they aren't drawn
+fn print_line(multi_progress: &MultiProgress, line: String) {
+    if multi_progress.is_hidden() {
+        println!("{}", line);
+    } else {
+        _ = multi_progress.println(line);
+    }
+}
+
+/// the spinner shown while the slots are scanned
+fn scan_progress_style() -> ProgressStyle {
+    ProgressStyle::with_template("{spinner:.cyan} scanning slots {pos}/{len}").unwrap()
+}
+
+/// the style of the upload progress bars
+fn progress_style() -> ProgressStyle {
+    progress_style_colored("cyan/blue")
+}
+
+/// the style a failed upload bar is left in, red so it stands out between the finished bars of the other slots
+fn failed_progress_style() -> ProgressStyle {
+    progress_style_colored("red/red")
+}
+
+/// the upload progress bar style with the bar in the given foreground/background colors
+fn progress_style_colored(colors: &str) -> ProgressStyle {
+    ProgressStyle::with_template(&format!(
+        "{{bar:40.{}}} {{pos:>7}}/{{len:7}} ({{eta}}) {{msg}}",
+        colors
+    ))
+    .unwrap()
+    .progress_chars("##-")
+    .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+        write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
+    })
+}
+
+/// the valid firmware filename patterns from the config, invalid ones are skipped with a warning
+fn filename_patterns(config: &Config) -> Vec<FilenamePattern> {
+    config
+        .firmware_patterns
+        .iter()
+        .filter_map(|pattern| {
+            FilenamePattern::parse(pattern)
+                .map_err(|err| {
+                    print_warning(format!(
+                        "Warning: ignoring firmware pattern in {}, {}",
+                        config::CONFIG_FILE,
+                        err
+                    ))
+                })
+                .ok()
+        })
+        .collect()
+}
+
+/// print what a firmware file is for and which modules in the modules file it would match
+fn print_firmware_info(path: &str, patterns: &[FilenamePattern]) {
+    let filename = std::path::Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let file = FirmwareFile::from_filename(filename, patterns).unwrap_or_else(|| {
+        err_n_die(format!("{} is not named like a firmware file", path).as_str())
+    });
+    let version = file.version;
+    let hardware = version.get_hardware();
+    println!("{}", file);
+    match version.module_type() {
+        Some(name) => println!("type: {} module version {}", name, hardware[3]),
+        None => println!("type: unknown"),
+    }
+    println!(
+        "hardware: {}-{}-{}-{}",
+        hardware[0], hardware[1], hardware[2], hardware[3]
+    );
+    println!("software: {}", version.get_software_version());
+    //only the name is needed for the above, the contents are shown when the file is there to read
+    if let Ok(contents) = fs::read_to_string(path) {
+        let lines: Vec<&str> = contents.lines().collect();
+        match srec_address_range(&lines) {
+            Ok(Some((start, end))) => println!(
+                "address range: {:#x}-{:#x} ({} bytes)",
+                start,
+                end.saturating_sub(1),
+                end - start
+            ),
+            Ok(None) => println!("address range: none, the file has no data records"),
+            Err(err) => println!("address range: unreadable, {}", err),
+        }
+    }
+
+    let modules_file = fs::read_to_string(modules_file_path()).unwrap_or_default();
+    let matching: Vec<(usize, FirmwareVersion)> = modules_file
+        .split('\n')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .enumerate()
+        .filter_map(|(i, firmware)| {
+            FirmwareVersion::from_filename(firmware.to_string()).map(|firmware| (i, firmware))
+        })
+        .filter(|(_, firmware)| firmware.get_hardware() == hardware)
+        .collect();
+    if matching.is_empty() {
+        println!("No module in {} matches this firmware", modules_file_path());
+        return;
+    }
+    println!("Matching modules in {}:", modules_file_path());
+    for (i, firmware) in matching {
+        let current = firmware.get_software_version();
+        let new = version.get_software_version();
+        println!(
+            "slot {}: currently {}, {}",
+            slot_label(i as u8 + 1),
+            current,
+            if current.is_blank() || new > current {
+                "would be updated"
+            } else if new == current {
+                "already at this version"
+            } else {
+                "would be downgraded with overwrite"
+            }
+        );
+    }
+}
+
+/// the modules file in MODULES_DIR, named by --modules-file or GO_MODULES_MODULES_FILE
+fn modules_file_path() -> String {
+    format!(
+        "{}{}",
+        MODULES_DIR,
+        MODULES_FILE_NAME
+            .get()
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_MODULES_FILE_NAME)
+    )
+}
+
+/// the spi clock speed to talk to the modules at, DEFAULT_SPI_SPEED until SPI_SPEED is set
+fn spi_speed() -> u32 {
+    SPI_SPEED.get().copied().unwrap_or(DEFAULT_SPI_SPEED)
+}
+
+/// the reset control path of a slot
+fn reset_path(slot: u8) -> String {
+    RESET_PATH
+        .get()
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_RESET_PATH)
+        .replace("{slot}", &slot.to_string())
+}
+
+/// the label of a slot as printed on the enclosure, the logical slot number is still used for the spidev and gpio selection
+fn slot_label(slot: u8) -> String {
+    SLOT_LABELS
+        .get()
+        .and_then(|labels| labels.get((slot as usize).wrapping_sub(1)))
+        .cloned()
+        .unwrap_or_else(|| slot.to_string())
+}
+
+/// get the logical slot of a slot entered by the user, either by its label or by its logical number
+fn parse_slot(arg: &str) -> Option<u8> {
+    SLOT_LABELS
+        .get()
+        .and_then(|labels| labels.iter().position(|label| label == arg))
+        .map(|i| i as u8 + 1)
+        .or_else(|| arg.parse::<u8>().ok())
+}
+
+/// print a success message, green when colors are enabled
+fn print_success(message: impl Display) {
+    println!("{}", style(message).green());
+}
+
+/// print the usage after a command line that can't be run, left out with --json as stderr only carries json then
+fn print_usage() {
+    if !JSON_MESSAGES.load(Ordering::Relaxed) {
+        eprintln!("{}", USAGE);
+    }
+}
+
+/// print a warning on stderr, yellow when colors are enabled
+fn print_warning(message: impl Display) {
+    if JSON_MESSAGES.load(Ordering::Relaxed) {
+        print_json_message(message, None, "warning");
+    } else {
+        eprintln!("{}", style(message).for_stderr().yellow());
+    }
+}
+
+/// print a message on stderr as a json object for --json, so a consumer can parse the errors like the output \
+/// a warning has the same shape as an error, with warning as its kind, a module saved by an interrupted run has
+/// interrupted as its kind
+fn print_json_message(message: impl Display, slot: Option<u8>, kind: &str) {
+    eprintln!(
+        "{{\"error\":{},\"slot\":{},\"kind\":{}}}",
+        json_string(&message.to_string()),
+        slot.map_or("null".to_string(), |slot| slot.to_string()),
+        json_string(kind)
+    );
+}
+
+/// print the error of a failed upload, with its slot and kind in the json of --json
+fn print_upload_error(err: &UploadError, message: impl Display) {
+    if JSON_MESSAGES.load(Ordering::Relaxed) {
+        print_json_message(message, Some(err.slot()), err.kind());
+    } else {
+        print_error(message);
+    }
+}
+
+/// the error for an upload that corrupted the module before --recover-on-corruption got it running the recovery image
+fn recovered_message(slot: u8, recovery: FirmwareVersion) -> String {
+    format!(
+        "Update failed, firmware was corrupted on slot {}, it runs the recovery image {} now",
+        slot_label(slot),
+        recovery.as_string()
+    )
+}
+
+/// print an error on stderr, red when colors are enabled
+fn print_error(message: impl Display) {
+    if JSON_MESSAGES.load(Ordering::Relaxed) {
+        print_json_message(message, None, "error");
+    } else {
+        eprintln!("{}", style(message).for_stderr().red());
     }
 }
 
 /// error out and restart nodered and go-simulink if required
 fn err_n_restart_services(nodered: bool, simulink: bool) -> ! {
+    //the device can only be open once, release it before the services that feed it start
+    watchdog::stop();
     if nodered {
         _ = Command::new("systemctl")
             .arg("start")
@@ -923,17 +3498,87 @@ fn err_n_restart_services(nodered: bool, simulink: bool) -> ! {
             .status();
     }
 
-    if simulink {
-        _ = Command::new("systemctl")
-            .arg("start")
-            .arg("go-simulink")
-            .status();
+    if simulink {
+        _ = Command::new("systemctl")
+            .arg("start")
+            .arg("go-simulink")
+            .status();
+    }
+    exit(-1);
+}
+
+/// the module spidevs and reset controls this user isn't allowed to open, opening them doesn't touch the modules
+fn permission_denied(controller: &ControllerTypes) -> Vec<String> {
+    let denied = |result: io::Result<fs::File>| matches!(result, Err(err) if err.kind() == io::ErrorKind::PermissionDenied);
+    let spidevs = fs::read_dir("/dev")
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+        .filter(|path| path.starts_with("/dev/spidev"))
+        .filter(|path| denied(fs::File::open(path)));
+    let resets = (1..=controller.slot_count())
+        .map(reset_path)
+        .filter(|path| denied(fs::OpenOptions::new().write(true).open(path)));
+    let mut paths: Vec<String> = spidevs.chain(resets).collect();
+    paths.sort();
+    paths
+}
+
+/// the processes other than this one that have a device starting with `device` open, as name (pid)
+fn spidev_users(device: &str) -> Vec<String> {
+    let own_pid = std::process::id().to_string();
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    processes
+        .filter_map(Result::ok)
+        .map(|process| process.file_name().to_string_lossy().into_owned())
+        .filter(|pid| pid.chars().all(|c| c.is_ascii_digit()) && *pid != own_pid)
+        .filter(|pid| {
+            fs::read_dir(format!("/proc/{}/fd", pid))
+                .map(|fds| {
+                    fds.filter_map(Result::ok).any(|fd| {
+                        fs::read_link(fd.path())
+                            .map(|target| target.to_string_lossy().starts_with(device))
+                            .unwrap_or(false)
+                    })
+                })
+                .unwrap_or(false)
+        })
+        .map(|pid| {
+            let name = fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+            format!("{} ({})", name.trim(), pid)
+        })
+        .collect()
+}
+
+/// send the modules file and the modules flashed so far to --report-url, if it was given
+fn post_report() {
+    if let Some(url) = REPORT_URL.get() {
+        report::post(
+            url,
+            &modules_file_json(
+                &fs::read_to_string(modules_file_path()).unwrap_or_default(),
+                &FLASHED
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()),
+                &[],
+                QrSide::Both,
+                FLASH_TIME.get().copied(),
+            ),
+        );
     }
-    exit(-1);
 }
 
 /// exit with a success code and restart the nodered and go-simulink services if required
 fn success(nodered: bool, simulink: bool) -> ! {
+    //the flashes themselves went fine, so everything was saved and only the exit code changes
+    if POST_FLASH_FAILED.load(Ordering::Relaxed) {
+        print_error("Error: a required post flash command failed");
+        err_n_restart_services(nodered, simulink);
+    }
+    watchdog::stop();
     if nodered {
         _ = Command::new("systemctl")
             .arg("start")
@@ -947,22 +3592,64 @@ fn success(nodered: bool, simulink: bool) -> ! {
             .arg("go-simulink")
             .status();
     }
+    post_report();
     exit(0);
 }
 
 /// error out without restarting any services
 fn err_n_die(message: &str) -> ! {
-    eprintln!("{}", message);
+    print_error(message);
+    watchdog::stop();
     exit(-1);
 }
 
-/// calculate an spi messages checksum
-fn calculate_checksum(message: &[u8], length: usize) -> u8 {
-    let mut checksum: u8 = 0;
-    for val in message.get(0..length).unwrap() {
-        checksum = checksum.wrapping_add(*val);
+/// the algorithm of the checksum byte that ends every bootloader frame, in both directions \
+/// a module is asked for its info with WrappingAdd, the algorithm its answer passes is used for the rest of the session
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Checksum {
+    /// the sum of the bytes, wrapping, used by the bootloaders so far
+    WrappingAdd,
+    /// crc8 with polynomial 0x07 and initial value 0, used by newer bootloader revisions
+    Crc8,
+}
+
+impl Checksum {
+    /// calculate an spi messages checksum over its first length bytes
+    fn calculate(self, message: &[u8], length: usize) -> u8 {
+        let message = message.get(0..length).unwrap();
+        match self {
+            Self::WrappingAdd => message
+                .iter()
+                .fold(0u8, |checksum, val| checksum.wrapping_add(*val)),
+            Self::Crc8 => message.iter().fold(0u8, |mut crc, val| {
+                crc ^= *val;
+                for _ in 0..8 {
+                    crc = if crc & 0x80 != 0 {
+                        (crc << 1) ^ 0x07
+                    } else {
+                        crc << 1
+                    };
+                }
+                crc
+            }),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::WrappingAdd => "wrapping add",
+            Self::Crc8 => "crc8",
+        }
+    }
+
+    /// the algorithm a frame with its checksum at the index passes, WrappingAdd when it happens to pass both
+    fn detect(rx_buf: &[u8], checksum: usize) -> Option<Self> {
+        [Self::WrappingAdd, Self::Crc8]
+            .into_iter()
+            .find(|algorithm| {
+                checksum < rx_buf.len() && rx_buf[checksum] == algorithm.calculate(rx_buf, checksum)
+            })
     }
-    checksum
 }
 
 /// turn a slice into a sized array to perform ::from_bytes() operations on
@@ -976,44 +3663,93 @@ where
     a
 }
 
-/// get module interrupt pin
-fn get_interrupt(chip: &str, line: u32, slot: u8) -> Option<AsyncLineEventHandle> {
-    let mut chip = Chip::new(chip)
-        .map_err(|_| eprintln!("Could not get slot {slot} interrupt chip"))
-        .ok()?;
-    let line = chip
-        .get_line(line)
-        .map_err(|_| eprintln!("Could not get slot {slot} interrupt line"))
-        .ok()?;
-    line.async_events(
-        LineRequestFlags::INPUT,
-        EventRequestFlags::FALLING_EDGE,
-        format!("module {slot} interrupt").as_str(),
-    )
-    .map_err(|err| eprintln!("Could not get slot {slot} interrupt line handle: {err}"))
-    .ok()
+/// wait for the interrupt to fire or the duration to pass, without an interrupt the full duration is waited \
+/// returns whether the interrupt fired
+async fn wait_interrupt<F: Future>(interrupt: Option<F>, duration: Duration) -> bool {
+    match interrupt {
+        Some(interrupt) => timeout(duration, interrupt).await.is_ok(),
+        None => {
+            time::sleep(duration).await;
+            false
+        }
+    }
 }
 
-/// get the current modules in the controller
-async fn get_modules(controller: &ControllerTypes) -> Vec<Module> {
+/// get the current modules in the controller \
+/// every slot is probed at once, the progress bar advances as each slot finishes
+async fn get_modules(controller: &ControllerTypes, progress: ProgressBar) -> Vec<Module> {
     let mut modules = Vec::with_capacity(8);
     let mut set = JoinSet::new();
     let controller = *controller;
     for i in 1..controller as usize {
-        set.spawn(async move { Module::new(i as u8, &controller).await });
+        set.spawn(async move { (i as u8, Module::new(i as u8, &controller).await) });
     }
-    for _ in 1..controller as usize {
-        if let Some(Ok(Some(module))) = set.join_next().await {
-            modules.push(module);
+    //one wedged slot must not stall the whole tool, the slots that finished in time are used
+    let deadline = time::Instant::now() + SCAN_TIMEOUT;
+    let mut pending: Vec<u8> = (1..controller as u8).collect();
+    progress.set_length(pending.len() as u64);
+    while !pending.is_empty() {
+        match time::timeout_at(deadline, set.join_next()).await {
+            Ok(Some(Ok((slot, module)))) => {
+                progress.inc(1);
+                pending.retain(|pending| *pending != slot);
+                modules.extend(module);
+            }
+            //a probe that panicked, its slot is as good as empty
+            Ok(Some(Err(_))) => {
+                progress.inc(1);
+                continue;
+            }
+            Ok(None) => break,
+            Err(_) => {
+                progress.suspend(|| {
+                    for slot in &pending {
+                        print_warning(format!(
+                            "Warning: slot {} did not respond within {} seconds, skipping it and keeping its entry in the modules file",
+                            slot_label(*slot),
+                            SCAN_TIMEOUT.as_secs()
+                        ));
+                    }
+                });
+                MISSED_SLOTS
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .extend(&pending);
+                //aborting could leave a module in reset, let the stuck probes end on their own
+                set.detach_all();
+                break;
+            }
         }
     }
+    //the upload bars take its place
+    progress.finish_and_clear();
     modules
 }
 
+/// reset every slot of the controller at once without talking to the modules, returns the slots that were reset
+async fn reset_all_modules(controller: ControllerTypes) -> Vec<u8> {
+    let mut set = JoinSet::new();
+    for slot in 1..=controller.slot_count() {
+        set.spawn(async move {
+            let module = Module::open(slot, &controller)?;
+            module.pulse_reset().await;
+            Some(slot)
+        });
+    }
+    let mut reset: Vec<u8> = set.join_all().await.into_iter().flatten().collect();
+    reset.sort();
+    reset
+}
+
 /// get the modules in the controller and save them
-async fn get_modules_and_save(controller: ControllerTypes) -> Vec<Module> {
-    let modules = get_modules(&controller).await;
-    let mut modules_out: Vec<Option<Module>> = match &controller {
+async fn get_modules_and_save(controller: ControllerTypes, progress: ProgressBar) -> Vec<Module> {
+    let modules = get_modules(&controller, progress).await;
+    save_modules(modules_by_slot(modules, &controller), &controller).0
+}
+
+/// put the modules at the index of their slot, empty slots are None
+fn modules_by_slot(modules: Vec<Module>, controller: &ControllerTypes) -> Vec<Option<Module>> {
+    let mut modules_out: Vec<Option<Module>> = match controller {
         ControllerTypes::ModulineDisplay => vec![None, None],
         ControllerTypes::ModulineIV => vec![None, None, None, None, None, None, None, None],
         ControllerTypes::ModulineMini => vec![None, None, None, None],
@@ -1022,30 +3758,258 @@ async fn get_modules_and_save(controller: ControllerTypes) -> Vec<Module> {
         let slot = module.slot;
         modules_out[(slot - 1) as usize] = Some(module);
     }
-    save_modules(modules_out, &controller)
+    modules_out
 }
 
-/// save all the modules to modules to /usr/lib/gocontroll/modules, None elements will be removed from the file
-fn save_modules(modules: Vec<Option<Module>>, controller: &ControllerTypes) -> Vec<Module> {
-    let modules_string =
-        if let Ok(contents) = std::fs::read_to_string("/usr/lib/gocontroll/modules") {
-            if contents.split('\n').count() == 4 {
-                // for some reason the file from older systems is messed up sometimes
-                contents
-            } else {
-                if std::fs::create_dir_all("/usr/lib/gocontroll/").is_err() {
-                    eprintln!("Could not create /usr/lib/gocontroll/");
-                }
-                controller.get_empty_modules_file()
+/// print the controller detected from the device tree hardware string and where the firmware is looked for, then exit
+fn print_controller_info(hardware_string: &str) -> ! {
+    println!(
+        "hardware string: \"{}\"",
+        hardware_string.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+    );
+    match ControllerInfo::parse(hardware_string) {
+        Ok(info) => {
+            println!("controller: {}", info.family);
+            println!(
+                "revision: {}",
+                info.revision.as_deref().unwrap_or("unknown")
+            );
+            println!("slots: {}", info.family.slot_count());
+            println!("firmware folder: {}", FIRMWARE_DIR);
+            exit(0);
+        }
+        Err(err) => {
+            print_error(format!("Error: {}", err));
+            println!("firmware folder: {}", FIRMWARE_DIR);
+            exit(-1);
+        }
+    }
+}
+
+/// print the name, value and origin of every setting, for the config command
+fn print_config(settings: &[(&str, String, String)]) {
+    let width = settings
+        .iter()
+        .map(|(name, ..)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, value, origin) in settings {
+        println!("{:<width$}  {} ({})", name, value, origin, width = width);
+    }
+}
+
+/// print the static spidev, interrupt and reset mapping of the slots of the controller
+fn print_slot_mapping(controller: &ControllerTypes) {
+    println!("{} slots:", controller);
+    for (slot, mapping) in (1..).zip(controller.slot_mapping()) {
+        println!(
+            "slot {}: {}, interrupt {} line {}, reset {}",
+            slot_label(slot),
+            mapping.spidev,
+            mapping.gpiochip,
+            mapping.line,
+            reset_path(slot)
+        );
+    }
+}
+
+/// the static slot mapping of the controller as json, for tools that build their own view of the slots
+fn slot_mapping_json(controller: &ControllerTypes) -> String {
+    let slots: Vec<String> = (1..)
+        .zip(controller.slot_mapping())
+        .map(|(slot, mapping)| {
+            format!(
+                "{{\"slot\":{},\"label\":{},\"spidev\":{},\"gpiochip\":{},\"line\":{},\"reset\":{}}}",
+                slot,
+                json_string(&slot_label(slot)),
+                json_string(mapping.spidev),
+                json_string(mapping.gpiochip),
+                mapping.line,
+                json_string(&reset_path(slot))
+            )
+        })
+        .collect();
+    format!(
+        "{{\"controller\":{},\"slot_count\":{},\"slots\":[{}]}}",
+        json_string(&controller.to_string()),
+        controller.slot_count(),
+        slots.join(",")
+    )
+}
+
+/// print which slots changed between two versions of the modules file
+fn print_modules_file_diff(old: &str, new: &str) {
+    const FIELDS: [&str; 4] = ["firmware", "manufacturer", "front QR", "rear QR"];
+    let old_lines: Vec<Vec<&str>> = old
+        .split('\n')
+        .map(|line| line.split(':').collect())
+        .collect();
+    let new_lines: Vec<Vec<&str>> = new
+        .split('\n')
+        .map(|line| line.split(':').collect())
+        .collect();
+    let mut changed = false;
+    for (field, name) in FIELDS.iter().enumerate() {
+        let old_values = old_lines.get(field).cloned().unwrap_or_default();
+        let new_values = new_lines.get(field).cloned().unwrap_or_default();
+        for slot in 0..old_values.len().max(new_values.len()) {
+            let old_value = old_values.get(slot).copied().unwrap_or_default();
+            let new_value = new_values.get(slot).copied().unwrap_or_default();
+            if old_value != new_value {
+                changed = true;
+                println!(
+                    "slot {} {}: {} -> {}",
+                    slot_label(slot as u8 + 1),
+                    name,
+                    if old_value.is_empty() {
+                        "empty"
+                    } else {
+                        old_value
+                    },
+                    if new_value.is_empty() {
+                        "empty"
+                    } else {
+                        new_value
+                    },
+                );
             }
+        }
+    }
+    if !changed {
+        println!("No changes");
+    }
+}
+
+/// describe the firmware of a slot by module type and version, for comparing slots
+fn describe_firmware(firmware: Option<FirmwareVersion>) -> String {
+    match firmware {
+        None => "empty".to_string(),
+        Some(firmware) if firmware.is_factory_blank() => "unprogrammed".to_string(),
+        Some(firmware) => match firmware.module_type() {
+            Some(name) => format!(
+                "{} module version {} sw: {}",
+                name,
+                firmware.get_hardware()[3],
+                firmware.get_software_version()
+            ),
+            None => format!("unknown: {}", firmware.as_string()),
+        },
+    }
+}
+
+/// describe the hardware a firmware is for, without its software version
+fn describe_hardware(firmware: &FirmwareVersion) -> String {
+    match firmware.module_type() {
+        Some(name) => format!("{} module version {}", name, firmware.get_hardware()[3]),
+        None => format!(
+            "unknown module {}",
+            firmware
+                .get_hardware()
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<String>>()
+                .join("-")
+        ),
+    }
+}
+
+/// print the slots where the scanned modules differ in type or firmware from the modules file of another controller
+fn print_inventory_diff(modules: &[Option<Module>], other_modules_file: &str) {
+    let other: Vec<Option<FirmwareVersion>> = other_modules_file
+        .split('\n')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .map(|firmware| FirmwareVersion::from_filename(firmware.to_string()))
+        .collect();
+    let mut changed = false;
+    for slot in 0..modules.len().max(other.len()) {
+        let here = modules
+            .get(slot)
+            .and_then(|module| module.as_ref())
+            .map(|module| module.firmware);
+        let there = other.get(slot).copied().flatten();
+        if here != there {
+            changed = true;
+            println!(
+                "slot {}: here {}, other {}",
+                slot_label(slot as u8 + 1),
+                describe_firmware(here),
+                describe_firmware(there)
+            );
+        }
+    }
+    if !changed {
+        println!("No differences");
+    }
+}
+
+/// save all the modules to the modules file, None elements will be removed from the file \
+/// returns the saved modules and the contents the file had before, to report what changed
+fn save_modules(
+    modules: Vec<Option<Module>>,
+    controller: &ControllerTypes,
+) -> (Vec<Module>, String) {
+    let records: Vec<Option<ModuleRecord>> = modules
+        .iter()
+        .map(|module| module.as_ref().map(Module::record))
+        .collect();
+    let previous = write_modules_file(&records, controller);
+    (modules.into_iter().flatten().collect(), previous)
+}
+
+/// merge the records into the modules file like save_modules, returns the contents the file had before \
+/// the file is replaced in one rename, so an exit halfway never leaves a partly written file behind
+fn write_modules_file(modules: &[Option<ModuleRecord>], controller: &ControllerTypes) -> String {
+    //the sigint handler saves the completed modules while the main thread might be saving too
+    let _saving = SAVE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let modules_string = if let Ok(contents) = std::fs::read_to_string(modules_file_path()) {
+        if contents.split('\n').count() == 4 {
+            // for some reason the file from older systems is messed up sometimes
+            contents
         } else {
-            if std::fs::create_dir_all("/usr/lib/gocontroll/").is_err() {
-                eprintln!("Could not create /usr/lib/gocontroll/");
+            if std::fs::create_dir_all(MODULES_DIR).is_err() {
+                print_error(format!("Could not create {}", MODULES_DIR));
             }
-            //if the file doesn't exist, generate a new template
             controller.get_empty_modules_file()
-        };
-    let mut lines: Vec<String> = modules_string
+        }
+    } else {
+        if std::fs::create_dir_all(MODULES_DIR).is_err() {
+            print_error(format!("Could not create {}", MODULES_DIR));
+        }
+        //if the file doesn't exist, generate a new template
+        controller.get_empty_modules_file()
+    };
+    //the slots index the columns below, a scan bug reporting one twice or out of range would silently mix up modules
+    if let Err(err) = check_slots(modules, controller.slot_count() as usize) {
+        debug_assert!(false, "{}", err);
+        print_error(format!("Not saving {}, {}", modules_file_path(), err));
+        return modules_string;
+    }
+    let contents = keep_missed_columns(
+        &merge_modules(&modules_string, modules),
+        &modules_string,
+        &missed_slots(),
+    );
+    let temporary = format!("{}.tmp", modules_file_path());
+    if std::fs::write(&temporary, contents)
+        .and_then(|()| std::fs::rename(&temporary, modules_file_path()))
+        .is_err()
+    {
+        print_error(format!(
+            "Could not save new layout to {}",
+            modules_file_path()
+        ))
+    }
+    modules_string
+}
+
+/// the modules file contents with the columns of the records replaced, None elements empty their column \
+/// the slots of the records have to be checked with check_slots first
+fn merge_modules(contents: &str, modules: &[Option<ModuleRecord>]) -> String {
+    let mut lines: Vec<String> = contents
         .split('\n')
         .map(|element| element.to_owned())
         .collect();
@@ -1089,169 +4053,1441 @@ fn save_modules(modules: Vec<Option<Module>>, controller: &ControllerTypes) -> V
             *rear_qrs.get_mut(i).unwrap() = "".to_string();
         }
     }
-    lines[0] = firmwares.join(":");
-    lines[1] = manufactures.join(":");
-    lines[2] = front_qrs.join(":");
-    lines[3] = rear_qrs.join(":");
+    lines[0] = firmwares.join(":");
+    lines[1] = manufactures.join(":");
+    lines[2] = front_qrs.join(":");
+    lines[3] = rear_qrs.join(":");
+    lines.join("\n")
+}
+
+/// the slots that missed the scan deadline so far
+fn missed_slots() -> Vec<u8> {
+    MISSED_SLOTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+/// the modules file contents with the columns of the missed slots taken back from the previous contents \
+/// a slot that didn't answer in time wasn't seen empty, what the file had for it is the best there is
+fn keep_missed_columns(contents: &str, previous: &str, missed: &[u8]) -> String {
+    let previous: Vec<Vec<&str>> = previous
+        .split('\n')
+        .map(|line| line.split(':').collect())
+        .collect();
+    contents
+        .split('\n')
+        .zip(0..)
+        .map(|(line, row)| {
+            let mut columns: Vec<&str> = line.split(':').collect();
+            for slot in missed {
+                let column = (*slot - 1) as usize;
+                if let (Some(kept), Some(target)) = (
+                    previous.get(row).and_then(|line| line.get(column)),
+                    columns.get_mut(column),
+                ) {
+                    *target = kept;
+                }
+            }
+            columns.join(":")
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// the modules file a full scan that found these modules saves, without reading or writing the file
+fn scanned_modules_file(modules: &[Module], controller: &ControllerTypes) -> String {
+    let mut records: Vec<Option<ModuleRecord>> = vec![None; controller.slot_count() as usize];
+    for module in modules {
+        records[(module.slot - 1) as usize] = Some(module.record());
+    }
+    merge_modules(&controller.get_empty_modules_file(), &records)
+}
+
+/// save the modules flashed so far when the run is stopped halfway, modules still being flashed are left out
+fn save_completed(controller: &ControllerTypes) {
+    let completed: Vec<Option<ModuleRecord>> = COMPLETED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .cloned()
+        .map(Some)
+        .collect();
+    if completed.is_empty() {
+        return;
+    }
+    if JSON_MESSAGES.load(Ordering::Relaxed) {
+        for module in completed.iter().flatten() {
+            print_json_message(
+                format!(
+                    "Interrupted, saving slot {} to {} in {}",
+                    slot_label(module.slot),
+                    module.firmware.as_string(),
+                    modules_file_path()
+                ),
+                Some(module.slot),
+                "interrupted",
+            );
+        }
+    } else {
+        eprintln!(
+            "Interrupted, saving the modules updated so far to {}:",
+            modules_file_path()
+        );
+        for module in completed.iter().flatten() {
+            eprintln!(
+                "slot {} to {}",
+                slot_label(module.slot),
+                module.firmware.as_string()
+            );
+        }
+    }
+    write_modules_file(&completed, controller);
+    post_report();
+}
+
+/// check that every module has its own slot from 1 to slot_count
+fn check_slots(modules: &[Option<ModuleRecord>], slot_count: usize) -> Result<(), String> {
+    let mut seen = vec![false; slot_count];
+    for module in modules.iter().flatten() {
+        match seen.get_mut((module.slot as usize).wrapping_sub(1)) {
+            None => {
+                return Err(format!(
+                    "a module reported slot {} but there are only {} slots",
+                    module.slot, slot_count
+                ))
+            }
+            Some(true) => {
+                return Err(format!(
+                    "more than one module reported slot {}",
+                    slot_label(module.slot)
+                ))
+            }
+            Some(seen) => *seen = true,
+        }
+    }
+    Ok(())
+}
+
+/// the firmware a slot had according to a modules file, only if the same module is still in that slot
+fn last_known_firmware(modules_file: &str, module: &Module) -> Option<FirmwareVersion> {
+    let lines: Vec<&str> = modules_file.split('\n').collect();
+    let index = (module.slot - 1) as usize;
+    if lines.get(2)?.split(':').nth(index)? != module.qr_front.to_string() {
+        return None;
+    }
+    let firmware =
+        FirmwareVersion::from_filename(lines.first()?.split(':').nth(index)?.to_string())?;
+    (firmware.get_hardware() == module.firmware.get_hardware()
+        && !firmware.get_software_version().is_blank())
+    .then_some(firmware)
+}
+
+/// reflash modules that were left wiped by an interrupted or failed upload with the firmware they had before,
+/// according to the modules file as it was before this scan. Asks first unless --auto-recover is given.
+#[allow(clippy::too_many_arguments)]
+async fn recover_wiped_modules(
+    modules: Vec<Module>,
+    permit: &WipePermit,
+    previous_modules_file: &str,
+    available_firmwares: &[FirmwareFile],
+    multi_progress: &MultiProgress,
+    style: &ProgressStyle,
+    options: &Options,
+    controller: &ControllerTypes,
+    cancel: &AtomicBool,
+) -> Vec<Module> {
+    let mut recovered = Vec::with_capacity(modules.len());
+    let mut flashed_before = false;
+    for mut module in modules {
+        if module.firmware.get_software_version() != SoftwareVersion::BLANK {
+            recovered.push(module);
+            continue;
+        }
+        let Some(firmware) =
+            last_known_firmware(previous_modules_file, &module).and_then(|version| {
+                available_firmwares
+                    .iter()
+                    .find(|file| file.version == version)
+            })
+        else {
+            print_warning(format!(
+                "Warning: slot {} has no firmware, probably from an interrupted upload, and its last known firmware is not available, overwrite it manually",
+                slot_label(module.slot)
+            ));
+            recovered.push(module);
+            continue;
+        };
+        let recover = if options.auto_recover {
+            true
+        } else if !options.agent && std::io::stdin().is_terminal() {
+            Confirm::new(&format!(
+                "slot {} has no firmware, probably from an interrupted upload, restore its last known firmware {}?",
+                slot_label(module.slot),
+                firmware.version.as_string()
+            ))
+            .with_default(true)
+            .prompt()
+            .unwrap_or(false)
+        } else {
+            print_warning(format!(
+                "Warning: slot {} has no firmware, probably from an interrupted upload, restore its last known firmware {} with --auto-recover",
+                slot_label(module.slot),
+                firmware.version.as_string()
+            ));
+            false
+        };
+        if !recover {
+            recovered.push(module);
+            continue;
+        }
+        if mem::replace(&mut flashed_before, true) {
+            time::sleep(options.inter_module_delay).await;
+        }
+        println!(
+            "restoring slot {} to {}",
+            slot_label(module.slot),
+            firmware.version.as_string()
+        );
+        match module
+            .overwrite_module_retrying(
+                permit,
+                firmware,
+                multi_progress.clone(),
+                style.clone(),
+                options,
+                cancel,
+            )
+            .await
+        {
+            Ok(()) => {
+                module.firmware = firmware.version;
+                print_success(format!(
+                    "succesfully restored slot {} to {}",
+                    slot_label(module.slot),
+                    firmware.version.as_string()
+                ));
+                recovered.extend(save_modules(vec![Some(module)], controller).0);
+            }
+            Err(err) => {
+                if let UploadError::FirmwareCorrupted(_) = err {
+                    module.wipe_module_error(permit).await;
+                }
+                print_error(format!(
+                    "Error: could not restore slot {}",
+                    slot_label(module.slot)
+                ));
+                recovered.push(module);
+            }
+        }
+    }
+    recovered
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn update_one_module(
+    mut module: Module,
+    permit: WipePermit,
+    available_firmwares: &[FirmwareFile],
+    multi_progress: MultiProgress,
+    style: ProgressStyle,
+    options: &Options,
+    controller: ControllerTypes,
+    cancel: &AtomicBool,
+    nodered: bool,
+    simulink: bool,
+) -> ! {
+    if let Err(err) = module.check_still_present().await {
+        print_error(format!("Update failed, {}", err));
+        err_n_restart_services(nodered, simulink);
+    }
+    match module
+        .update_module(
+            &permit,
+            available_firmwares,
+            multi_progress,
+            style,
+            options,
+            cancel,
+        )
+        .await
+    {
+        Ok(Ok((module, location))) => {
+            print_success(format!(
+                "Succesfully updated slot {} to {} from {}",
+                slot_label(module.slot),
+                module.firmware.as_string(),
+                location
+            ));
+            save_modules(vec![Some(module)], &controller);
+            success(nodered, simulink);
+        }
+        Err(err) => {
+            match err {
+                UploadError::FirmwareCorrupted(slot) => {
+                    print_upload_error(
+                        &err,
+                        format!(
+                            "Update failed, firmware is corrupted on slot {}",
+                            slot_label(slot)
+                        ),
+                    );
+                    err_n_die("could not restart nodered and go-simulink services due to corrupted firmware.");
+                }
+                UploadError::FirmwareUntouched(slot) => {
+                    print_upload_error(&err, format!("Update failed on slot {}", slot_label(slot)));
+                    err_n_restart_services(nodered, simulink);
+                }
+                UploadError::Recovered(slot, recovery) => {
+                    print_upload_error(&err, recovered_message(slot, recovery));
+                    err_n_restart_services(nodered, simulink);
+                }
+                UploadError::Cancelled(slot) => {
+                    print_upload_error(
+                        &err,
+                        format!("Update cancelled on slot {}", slot_label(slot)),
+                    );
+                    err_n_restart_services(nodered, simulink);
+                }
+            }
+        }
+        Ok(Err(module)) => {
+            print_warning(format!(
+                "Update failed, no update available for slot {}: {}",
+                slot_label(module.slot),
+                module.firmware.as_string()
+            ));
+            err_n_restart_services(nodered, simulink);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn update_all_modules(
+    modules: Vec<Module>,
+    permit: WipePermit,
+    available_firmwares: &[FirmwareFile],
+    multi_progress: &MultiProgress,
+    style: &ProgressStyle,
+    options: &Options,
+    controller: ControllerTypes,
+    cancel: &Arc<AtomicBool>,
+    nodered: bool,
+    simulink: bool,
+) -> ! {
+    //a controller without modules has nothing up to date either, so this isn't reported as "no updates found"
+    if modules.is_empty() {
+        print_error("No modules present to update");
+        err_n_restart_services(nodered, simulink);
+    }
+    //the modules that got updated and on which attempt
+    let mut updated: Vec<(Module, u32, String, Duration)> = Vec::with_capacity(modules.len());
+    let mut failed: Vec<UploadError> = Vec::new();
+    //the modules update_module left alone, pinned, blank, up to date or without firmware for their hardware
+    let mut skipped: Vec<Module> = Vec::new();
+    //a run without --resume starts over, the slots it finishes are recorded from scratch
+    let mut finished = if options.resume {
+        load_update_progress()
+    } else {
+        Vec::new()
+    };
+    save_update_progress(&finished);
+    let (resumed, mut pending): (Vec<Module>, Vec<Module>) = modules
+        .into_iter()
+        .partition(|module| finished.contains(&module.slot));
+    for module in resumed {
+        println!(
+            "slot {} was already updated by the interrupted update, skipping it",
+            slot_label(module.slot)
+        );
+        skipped.push(module);
+    }
+    //every module is flashed in its own task, they all borrow the one permit
+    let permit = Arc::new(permit);
+    let batch_start = Instant::now();
+    for attempt in 1..=options.attempts {
+        let mut set = JoinSet::new();
+        for module in pending.drain(..) {
+            let permit = permit.clone();
+            let available_firmwares = available_firmwares.to_owned();
+            let multi_progress = multi_progress.clone();
+            let style = style.clone();
+            let options = options.clone();
+            let cancel = cancel.clone();
+            set.spawn(async move {
+                let start = Instant::now();
+                let result = module
+                    .update_module(
+                        &permit,
+                        available_firmwares.as_slice(),
+                        multi_progress,
+                        style,
+                        &options,
+                        &cancel,
+                    )
+                    .await;
+                (result, start.elapsed())
+            });
+        }
+        while let Some(joined) = set.join_next().await {
+            let (result, flash_time) = joined.unwrap();
+            match result {
+                Ok(Ok((module, location))) => {
+                    //module updated, recorded right away so an interruption after this keeps it
+                    finished.push(module.slot);
+                    save_update_progress(&finished);
+                    updated.push((module, attempt, location, flash_time))
+                }
+                Err(err) => {
+                    print_upload_error(
+                        &err,
+                        match err {
+                            UploadError::FirmwareCorrupted(slot) => format!(
+                                "Update failed, firmware is corrupted on slot {}",
+                                slot_label(slot)
+                            ),
+                            UploadError::FirmwareUntouched(slot) => {
+                                format!("Update failed on slot {}", slot_label(slot))
+                            }
+                            UploadError::Recovered(slot, recovery) => {
+                                recovered_message(slot, recovery)
+                            }
+                            UploadError::Cancelled(slot) => {
+                                format!("Update cancelled on slot {}", slot_label(slot))
+                            }
+                        },
+                    );
+                    failed.push(err);
+                }
+                Ok(Err(module)) => skipped.push(module), //no new firmwares available
+            }
+        }
+        if failed.is_empty() || attempt == options.attempts || cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        //scan the failed slots again, a module that can't be found anymore stays failed
+        println!(
+            "retrying the failed slots, attempt {} of {}",
+            attempt + 1,
+            options.attempts
+        );
+        for err in mem::take(&mut failed) {
+            //the rescan resets the modules one by one, right after the previous flashes ended
+            time::sleep(options.inter_module_delay).await;
+            match Module::new(err.slot(), &controller).await {
+                Some(module) => pending.push(module),
+                None => failed.push(err),
+            }
+        }
+    }
+    let flash_time = batch_start.elapsed();
+    _ = FLASH_TIME.set(flash_time);
+    let firmware_corrupted = failed
+        .iter()
+        .any(|err| matches!(err, UploadError::FirmwareCorrupted(_)));
+    let cancelled = failed
+        .iter()
+        .any(|err| matches!(err, UploadError::Cancelled(_)));
+    if !updated.is_empty() {
+        print_success("Succesfully updated:");
+        for (module, attempt, location, module_time) in &updated {
+            if options.attempts > 1 {
+                print_success(format!(
+                    "slot {} to {} from {} on attempt {} in {:.1}s, {}",
+                    slot_label(module.slot),
+                    module.firmware.as_string(),
+                    location,
+                    attempt,
+                    module_time.as_secs_f64(),
+                    describe_wipe(module.wipe_time)
+                ));
+            } else {
+                print_success(format!(
+                    "slot {} to {} from {} in {:.1}s, {}",
+                    slot_label(module.slot),
+                    module.firmware.as_string(),
+                    location,
+                    module_time.as_secs_f64(),
+                    describe_wipe(module.wipe_time)
+                ));
+            }
+        }
+    }
+    if !updated.is_empty() || !failed.is_empty() {
+        println!("flashing took {:.1}s in total", flash_time.as_secs_f64());
+    }
+    //the skipped modules that update could have touched, and those of them it had no firmware for
+    let eligible: Vec<&Module> = skipped
+        .iter()
+        .filter(|module| !module.firmware.is_factory_blank() && !options.is_pinned(module.slot))
+        .collect();
+    let unmatched: Vec<&Module> = eligible
+        .iter()
+        .copied()
+        .filter(|module| {
+            !available_firmwares
+                .iter()
+                .any(|firmware| firmware.version.get_hardware() == module.firmware.get_hardware())
+        })
+        .collect();
+    for module in &unmatched {
+        print_warning(format!(
+            "No firmware staged in {} for the {} in slot {}",
+            FIRMWARE_DIR,
+            describe_hardware(&module.firmware),
+            slot_label(module.slot)
+        ));
+    }
+    if updated.is_empty() && failed.is_empty() {
+        if !unmatched.is_empty() && unmatched.len() == eligible.len() {
+            print_warning("No updates found, none of the modules in this controller have firmware staged for their hardware.");
+        } else if !unmatched.is_empty() {
+            print_warning(
+                "No updates found, the other modules already have the newest staged firmware.",
+            );
+        } else if !eligible.is_empty() {
+            print_warning("No updates found, the modules in this controller already have the newest staged firmware.");
+        } else {
+            print_warning("No updates found for the modules in this controller.");
+        }
+    }
+    if !failed.is_empty() && options.attempts > 1 {
+        print_error(format!(
+            "Still failing after {} attempts: slot {}",
+            options.attempts,
+            failed
+                .iter()
+                .map(|err| slot_label(err.slot()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+    //the marker is only needed to resume a run that didn't finish
+    if failed.is_empty() {
+        _ = fs::remove_file(PROGRESS_FILE);
+    }
+    let any_updated = !updated.is_empty();
+    let (_, previous_modules_file) = save_modules(
+        updated
+            .into_iter()
+            .map(|(module, ..)| Some(module))
+            .collect(),
+        &controller,
+    );
+    //the file was saved by the scan, so it held the firmware from right before the update
+    if any_updated {
+        println!("Changes to {}:", modules_file_path());
+        print_modules_file_diff(
+            &previous_modules_file,
+            &fs::read_to_string(modules_file_path()).unwrap_or_default(),
+        );
+    }
+    if firmware_corrupted {
+        err_n_die("could not restart nodered and go-simulink services due to corrupted firmware.");
+    }
+    if cancelled {
+        //the modules updated before the cancel are saved above, report those too
+        post_report();
+        err_n_restart_services(nodered, simulink);
+    }
+
+    success(nodered, simulink);
+}
+
+/// whether an srec line is an S1, S2 or S3 record, the ones that carry the firmware itself
+fn is_data_record(line: &str) -> bool {
+    matches!(line.get(0..2), Some("S1" | "S2" | "S3"))
+}
+
+/// check that srec lines are records the upload can send: every line a record with a matching byte count and checksum,
+/// at least one data record, and a single S7 record at the end, which is where the upload stops
+fn check_srec(lines: &[&str]) -> Result<(), String> {
+    for (i, line) in lines.iter().enumerate() {
+        let record = line.trim_end();
+        if !record.starts_with('S')
+            || !record
+                .get(1..2)
+                .is_some_and(|kind| kind.chars().all(|c| c.is_ascii_digit()))
+        {
+            return Err(format!("line {} is not an srec record", i + 1));
+        }
+        let bytes = (2..record.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(record.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .filter(|bytes| bytes.len() >= 2 && bytes[0] as usize == bytes.len() - 1)
+            .ok_or_else(|| format!("line {} doesn't have the length its byte count says", i + 1))?;
+        //the checksum is the ones' complement of the sum of the byte count, address and data
+        let sum = bytes[..bytes.len() - 1]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        if !sum != bytes[bytes.len() - 1] {
+            return Err(format!("line {} doesn't match its checksum", i + 1));
+        }
+    }
+    if !lines.iter().any(|line| is_data_record(line)) {
+        return Err("it has no data records".to_string());
+    }
+    match lines.iter().position(|line| line.starts_with("S7")) {
+        Some(position) if position != lines.len() - 1 => Err(format!(
+            "the S7 record on line {} is not the last record",
+            position + 1
+        )),
+        Some(_) => Ok(()),
+        None => Err("it has no S7 record to end with".to_string()),
+    }
+}
 
-    if std::fs::write("/usr/lib/gocontroll/modules", lines.join("\n")).is_err() {
-        eprintln!("Could not save new layout to /usr/lib/gocontroll/modules")
+/// the lowest address the data records (S1, S2 and S3) of srec lines write and the address after the highest byte they
+/// write, None when there are no data records, an error names the first data record that can't be parsed
+fn srec_address_range(lines: &[&str]) -> Result<Option<(u32, u32)>, String> {
+    let mut range: Option<(u32, u32)> = None;
+    for (i, line) in lines.iter().enumerate() {
+        let record = line.trim();
+        let address_bytes = match record.get(0..2) {
+            Some("S1") => 2,
+            Some("S2") => 3,
+            Some("S3") => 4,
+            _ => continue,
+        };
+        let parsed = (|| {
+            let count = usize::from_str_radix(record.get(2..4)?, 16).ok()?;
+            let address = u32::from_str_radix(record.get(4..4 + address_bytes * 2)?, 16).ok()?;
+            //the byte count covers the address, the data and the checksum
+            let data = count.checked_sub(address_bytes + 1)? as u32;
+            Some((address, address.checked_add(data)?))
+        })();
+        let Some((start, end)) = parsed else {
+            return Err(format!("line {} is not a valid data record", i + 1));
+        };
+        range = Some(match range {
+            Some((low, high)) => (low.min(start), high.max(end)),
+            None => (start, end),
+        });
     }
-    modules.into_iter().flatten().collect()
+    Ok(range)
 }
 
-async fn update_one_module(
-    module: Module,
-    available_firmwares: &[FirmwareVersion],
-    multi_progress: MultiProgress,
-    style: ProgressStyle,
+/// the data bytes of the S0 header record of srec lines, None without a header
+fn srec_header_data(lines: &[&str]) -> Option<Vec<u8>> {
+    let record = lines.iter().find(|line| line.starts_with("S0"))?.trim();
+    let bytes = (2..record.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(record.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    //byte count, 2 address bytes, the header data and the checksum
+    Some(bytes.get(3..bytes.len().checked_sub(1)?)?.to_vec())
+}
+
+/// the text in the S0 header record of srec lines, None without a header or when it isn't text
+fn srec_header(lines: &[&str]) -> Option<String> {
+    header_text(srec_header_data(lines)?)
+}
+
+fn header_text(data: Vec<u8>) -> Option<String> {
+    let text = String::from_utf8(data).ok()?;
+    text.chars()
+        .all(|c| !c.is_control() || c.is_whitespace() || c == '\0')
+        .then(|| {
+            text.trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                .to_string()
+        })
+}
+
+/// a firmware version named in an srec header like a firmware filename, such as 20-10-1-5-0-0-9.srec
+fn header_firmware_version(header: &str) -> Option<FirmwareVersion> {
+    header
+        .split(|c: char| c.is_whitespace() || c == '/' || c == '\\')
+        //from_filename takes a lone number as a version too, a header word only names one with all seven numbers
+        .filter(|word| {
+            word.split('.')
+                .next()
+                .unwrap_or_default()
+                .split('-')
+                .count()
+                == 7
+        })
+        .find_map(|word| FirmwareVersion::from_filename(word.to_string()))
+}
+
+/// the firmware version the S0 header names once every two bytes of it are swapped back, when it names none as it is \
+/// a toolchain writing 16 bit words in the other byte order swaps the header like it swaps the data, a firmware
+/// filename in the header is the only part of the file that can be recognized that way
+fn byte_swapped_header_version(lines: &[&str]) -> Option<FirmwareVersion> {
+    let data = srec_header_data(lines)?;
+    if header_text(data.clone())
+        .as_deref()
+        .and_then(header_firmware_version)
+        .is_some()
+    {
+        return None;
+    }
+    let swapped: Vec<u8> = data
+        .chunks(2)
+        .flat_map(|pair| pair.iter().rev().copied())
+        .collect();
+    header_firmware_version(&header_text(swapped)?)
+}
+
+/// find a firmware file by its exact filename, or by the version in a filename named like 20-10-1-5-0-0-9.srec
+fn find_firmware(arg: &str, available_firmwares: &[FirmwareFile]) -> Result<FirmwareFile, String> {
+    if let Some(firmware) = available_firmwares.iter().find(|file| file.filename == arg) {
+        return Ok(firmware.clone());
+    }
+    let version = FirmwareVersion::from_filename(arg.to_string())
+        .ok_or_else(|| format!("Invalid firmware entered {}", arg))?;
+    available_firmwares
+        .iter()
+        .find(|file| file.version == version)
+        .cloned()
+        .ok_or_else(|| format!("{}{} does not exist", FIRMWARE_DIR, arg))
+}
+
+/// overwrite the slots listed in a slot,firmware csv file at the same time \
+/// every row is checked against the modules in the controller first, a single invalid row means nothing is flashed
+#[allow(clippy::too_many_arguments)]
+async fn overwrite_from_csv(
+    path: &str,
+    permit: WipePermit,
+    modules: Vec<Module>,
+    available_firmwares: &[FirmwareFile],
+    multi_progress: &MultiProgress,
+    style: &ProgressStyle,
+    options: &Options,
     controller: ControllerTypes,
+    cancel: &Arc<AtomicBool>,
     nodered: bool,
     simulink: bool,
 ) -> ! {
-    match module
-        .update_module(available_firmwares, multi_progress, style)
-        .await
-    {
-        Ok(Ok(module)) => {
-            println!(
-                "Succesfully updated slot {} to {}",
-                module.slot,
-                module.firmware.as_string()
-            );
-            save_modules(vec![Some(module)], &controller);
-            success(nodered, simulink);
+    let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+        print_error(format!("Could not read {}: {}", path, err));
+        err_n_restart_services(nodered, simulink);
+    });
+    let mut modules = modules_by_slot(modules, &controller);
+    //(row number, module, firmware) of every valid row
+    let mut rows: Vec<(usize, Module, FirmwareFile)> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let row = i + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
         }
-        Err(err) => match err {
-            UploadError::FirmwareCorrupted(slot) => {
-                err_n_die(
-                    format!("Update failed, firmware is corrupted on slot {}", slot).as_str(),
-                );
-            }
-            UploadError::FirmwareUntouched(slot) => {
-                eprintln!("Update failed on slot {}", slot);
-                err_n_restart_services(nodered, simulink);
+        let fields: Vec<&str> = line
+            .split(',')
+            .map(|field| field.trim().trim_matches('"'))
+            .collect();
+        let [slot, firmware] = fields.as_slice() else {
+            errors.push(format!(
+                "row {}: expected slot,firmware but got {}",
+                row, line
+            ));
+            continue;
+        };
+        //a header naming the columns
+        if row == 1 && slot.eq_ignore_ascii_case("slot") {
+            continue;
+        }
+        let Some(slot) =
+            parse_slot(slot).filter(|slot| (1..=controller.slot_count()).contains(slot))
+        else {
+            errors.push(format!("row {}: no slot {} in this controller", row, slot));
+            continue;
+        };
+        if rows.iter().any(|(_, module, _)| module.slot == slot) {
+            errors.push(format!(
+                "row {}: slot {} is listed more than once",
+                row,
+                slot_label(slot)
+            ));
+            continue;
+        }
+        let firmware = match find_firmware(firmware, available_firmwares) {
+            Ok(firmware) => firmware,
+            Err(err) => {
+                errors.push(format!("row {}: {}", row, err));
+                continue;
             }
-        },
-        Ok(Err(module)) => {
-            eprintln!(
-                "Update failed, no update available for slot {}: {}",
-                module.slot,
+        };
+        let Some(module) = modules[(slot - 1) as usize].take() else {
+            errors.push(format!(
+                "row {}: no module found in slot {}",
+                row,
+                slot_label(slot)
+            ));
+            continue;
+        };
+        if options.is_pinned(slot) && !options.force {
+            errors.push(format!(
+                "row {}: slot {} is pinned, use --force to overwrite it anyway",
+                row,
+                slot_label(slot)
+            ));
+            continue;
+        }
+        //a factory blank module doesn't know its hardware yet, so any firmware could be the right one
+        if !module.firmware.is_factory_blank()
+            && firmware.version.get_hardware() != module.firmware.get_hardware()
+        {
+            errors.push(format!(
+                "row {}: {} is not made for the module in slot {} with firmware {}",
+                row,
+                firmware,
+                slot_label(slot),
                 module.firmware.as_string()
-            );
-            err_n_restart_services(nodered, simulink);
+            ));
+            continue;
+        }
+        rows.push((row, module, firmware));
+    }
+    if !errors.is_empty() {
+        for err in &errors {
+            print_error(err);
         }
+        print_error(format!(
+            "Nothing flashed, {} rows of {} are invalid",
+            errors.len(),
+            path
+        ));
+        err_n_restart_services(nodered, simulink);
+    }
+    if rows.is_empty() {
+        print_warning(format!("No slots listed in {}", path));
+        success(nodered, simulink);
     }
+
+    overwrite_concurrently(
+        rows.into_iter()
+            .map(|(row, module, firmware)| (format!("row {}: ", row), module, firmware))
+            .collect(),
+        permit,
+        multi_progress,
+        style,
+        options,
+        controller,
+        cancel,
+        nodered,
+        simulink,
+    )
+    .await
 }
 
-async fn update_all_modules(
-    modules: Vec<Module>,
-    available_firmwares: &[FirmwareVersion],
+/// overwrite every module with its firmware at the same time, then save the modules and exit \
+/// the outcome of every module is printed in the order of the jobs, each behind the prefix of its job
+#[allow(clippy::too_many_arguments)]
+async fn overwrite_concurrently(
+    jobs: Vec<(String, Module, FirmwareFile)>,
+    permit: WipePermit,
     multi_progress: &MultiProgress,
     style: &ProgressStyle,
+    options: &Options,
     controller: ControllerTypes,
+    cancel: &Arc<AtomicBool>,
     nodered: bool,
     simulink: bool,
 ) -> ! {
-    let mut upload_results = Vec::with_capacity(modules.len());
-    let mut new_modules = Vec::with_capacity(modules.len());
-    let mut firmware_corrupted = false;
+    //every module is flashed in its own task, they all borrow the one permit
+    let permit = Arc::new(permit);
     let mut set = JoinSet::new();
-    for module in modules {
-        let available_firmwares = available_firmwares.to_owned();
+    for (job, (prefix, mut module, firmware)) in jobs.into_iter().enumerate() {
+        let permit = permit.clone();
         let multi_progress = multi_progress.clone();
         let style = style.clone();
+        let options = options.clone();
+        let cancel = cancel.clone();
         set.spawn(async move {
-            module
-                .update_module(available_firmwares.as_slice(), multi_progress, style)
-                .await
+            let result = module
+                .overwrite_module_retrying(
+                    &permit,
+                    &firmware,
+                    multi_progress,
+                    style,
+                    &options,
+                    &cancel,
+                )
+                .await;
+            if let Err(UploadError::FirmwareCorrupted(slot)) = result {
+                print_error(format!(
+                    "firmware upload critically failed on slot {}, wiping firmware...",
+                    slot_label(slot)
+                ));
+                module.wipe_module_error(&permit).await;
+            }
+            (job, prefix, module, firmware, result)
         });
     }
-    for _ in 0..set.len() {
-        upload_results.push(set.join_next().await.unwrap().unwrap());
-    }
-    for result in upload_results {
+    let mut results = set.join_all().await;
+    results.sort_by_key(|(job, ..)| *job);
+
+    let mut updated = Vec::with_capacity(results.len());
+    let mut firmware_corrupted = false;
+    let mut firmware_untouched = false;
+    for (_, prefix, mut module, firmware, result) in results {
         match result {
-            Ok(Ok(module)) => {
-                //module updated
-                new_modules.push(Some(module))
+            Ok(()) => {
+                print_success(format!(
+                    "{}succesfully updated slot {} from {} to {}",
+                    prefix,
+                    slot_label(module.slot),
+                    module.firmware.as_string(),
+                    firmware.version.as_string()
+                ));
+                module.firmware = firmware.version;
+                updated.push(Some(module));
+            }
+            Err(ref err @ UploadError::FirmwareCorrupted(slot)) => {
+                print_upload_error(
+                    err,
+                    format!(
+                        "{}update failed, firmware is corrupted on slot {}",
+                        prefix,
+                        slot_label(slot)
+                    ),
+                );
+                firmware_corrupted = true;
+            }
+            Err(ref err @ UploadError::FirmwareUntouched(slot)) => {
+                print_upload_error(
+                    err,
+                    format!("{}update failed on slot {}", prefix, slot_label(slot)),
+                );
+                firmware_untouched = true;
+            }
+            Err(ref err @ UploadError::Recovered(slot, recovery)) => {
+                print_upload_error(
+                    err,
+                    format!("{}{}", prefix, recovered_message(slot, recovery)),
+                );
+                module.firmware = recovery;
+                updated.push(Some(module));
+                firmware_untouched = true;
+            }
+            Err(ref err @ UploadError::Cancelled(slot)) => {
+                print_upload_error(
+                    err,
+                    format!("{}update cancelled on slot {}", prefix, slot_label(slot)),
+                );
+                firmware_untouched = true;
             }
-            Err(err) => match err {
-                UploadError::FirmwareCorrupted(slot) => {
-                    eprintln!("Update failed, firmware is corrupted on slot {}", slot);
-                    firmware_corrupted = true;
-                }
-                UploadError::FirmwareUntouched(slot) => {
-                    eprintln!("Update failed on slot {}", slot);
-                }
-            },
-            Ok(Err(_)) => (), //no new firmwares available
         }
     }
-    if !new_modules.is_empty() {
-        println!("Succesfully updated:");
-        for module in &new_modules {
+    save_modules(updated, &controller);
+    if firmware_corrupted {
+        err_n_die("could not restart nodered and go-simulink services due to corrupted firmware.");
+    }
+    if firmware_untouched {
+        err_n_restart_services(nodered, simulink);
+    }
+    success(nodered, simulink);
+}
+
+/// overwrite every module of one type with the firmware of one software version at the same time \
+/// the slots with another type of module, a factory blank module or a pinned module are skipped with the reason, a
+/// module of the type without a firmware file for the version means nothing is flashed
+#[allow(clippy::too_many_arguments)]
+async fn overwrite_type(
+    module_type: &str,
+    version: SoftwareVersion,
+    permit: WipePermit,
+    modules: Vec<Module>,
+    available_firmwares: &[FirmwareFile],
+    multi_progress: &MultiProgress,
+    style: &ProgressStyle,
+    options: &Options,
+    controller: ControllerTypes,
+    cancel: &Arc<AtomicBool>,
+    nodered: bool,
+    simulink: bool,
+) -> ! {
+    let mut jobs: Vec<(String, Module, FirmwareFile)> = Vec::new();
+    let mut errors: Vec<String> = Vec::new();
+    for (slot, module) in (1..).zip(modules_by_slot(modules, &controller)) {
+        let Some(module) = module else {
+            println!("slot {}: skipped, no module found", slot_label(slot));
+            continue;
+        };
+        if module.firmware.is_factory_blank() {
             println!(
-                "slot {} to {}",
-                module.as_ref().unwrap().slot,
-                module.as_ref().unwrap().firmware.as_string()
+                "slot {}: skipped, the factory blank module doesn't know its type yet",
+                slot_label(slot)
+            );
+            continue;
+        }
+        if !module.firmware.is_module_type(module_type) {
+            println!(
+                "slot {}: skipped, {} is not a {}",
+                slot_label(slot),
+                module
+                    .firmware
+                    .module_type_short()
+                    .unwrap_or("unknown module"),
+                module_type
+            );
+            continue;
+        }
+        if options.is_pinned(slot) && !options.force {
+            println!(
+                "slot {}: skipped, pinned, use --force to overwrite it anyway",
+                slot_label(slot)
             );
+            continue;
         }
-    } else if !firmware_corrupted {
-        eprintln!("No updates found for the modules in this controller.");
+        let Some(firmware) = available_firmwares.iter().find(|firmware| {
+            firmware.version.get_hardware() == module.firmware.get_hardware()
+                && firmware.version.get_software_version() == version
+        }) else {
+            errors.push(format!(
+                "slot {}: no firmware {} for the module with firmware {} in {}",
+                slot_label(slot),
+                version,
+                module.firmware.as_string(),
+                FIRMWARE_DIR
+            ));
+            continue;
+        };
+        jobs.push((String::new(), module, firmware.clone()));
     }
-    save_modules(new_modules, &controller);
-    if firmware_corrupted {
-        err_n_die("could not restart nodered and go-simulink services due to corrupted firmware.");
+    if !errors.is_empty() {
+        for err in &errors {
+            print_error(err);
+        }
+        print_error(format!(
+            "Nothing flashed, {} modules of type {} have no firmware {}",
+            errors.len(),
+            module_type,
+            version
+        ));
+        err_n_restart_services(nodered, simulink);
     }
-
-    success(nodered, simulink);
+    if jobs.is_empty() {
+        print_warning(format!("No {} modules to overwrite", module_type));
+        success(nodered, simulink);
+    }
+    overwrite_concurrently(
+        jobs,
+        permit,
+        multi_progress,
+        style,
+        options,
+        controller,
+        cancel,
+        nodered,
+        simulink,
+    )
+    .await
 }
 
 #[tokio::main(flavor = "multi_thread", worker_threads = 3)]
 async fn main() {
-    println!("GOcontroll module management utility V{}", VERSION);
-    #[cfg(debug_assertions)]
-    println!("Debug version");
+    let (mut options, args) = Options::parse(env::args()).unwrap_or_else(|err| {
+        //the options didn't parse, but a --json among them still asks for json
+        JSON_MESSAGES.store(env::args().any(|arg| arg == "--json"), Ordering::Relaxed);
+        print_error(err);
+        print_usage();
+        exit(-1);
+    });
+    //console already disables colors for NO_COLOR and when not writing to a terminal
+    if options.no_color {
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    transport::set_trace(options.trace_spi);
+    JSON_MESSAGES.store(options.json, Ordering::Relaxed);
+    _ = VERSION_STYLE.set(options.version_style);
+    if options.capabilities {
+        //keep stdout pure json for the tools parsing it
+        print_capabilities();
+        exit(0);
+    }
+    //every command is forwarded with --remote, also the ones that only read files, those files are on the remote
+    if !options.remote.is_empty() {
+        remote::run(&options.remote, progress_style());
+    }
+    //commands that don't touch the modules are handled before any services are stopped
+    match args.get(1).map(String::as_str) {
+        Some("stats") => {
+            print_stats();
+            exit(0);
+        }
+        Some("flash-count") => {
+            let qr = args.get(2).unwrap_or_else(|| {
+                print_error("flash-count requires a QR code");
+                print_usage();
+                exit(-1);
+            });
+            print_flash_count(qr);
+            exit(0);
+        }
+        Some("firmware-info") => {
+            let path = args.get(2).unwrap_or_else(|| {
+                print_error("firmware-info requires a firmware file");
+                print_usage();
+                exit(-1);
+            });
+            let config = Config::load().unwrap_or_else(|err| {
+                print_warning(format!("Warning: ignoring config, {}", err));
+                Config::default()
+            });
+            print_firmware_info(path, &filename_patterns(&config));
+            exit(0);
+        }
+        //the bootloader only knows info (9), exit (19), wipe (29), firmware line (39) and status (49) messages
+        Some("dump") => err_n_die(
+            "Dumping firmware is not supported, the module bootloader has no command to read back its flash",
+        ),
+        _ => (),
+    }
+    if options.agent {
+        remote::announce_agent();
+    }
+    if let Some(url) = &options.report_url {
+        if !report::SUPPORTED {
+            err_n_die(
+                "--report-url is not available, go-modules was built without the report feature",
+            );
+        }
+        _ = REPORT_URL.set(url.clone());
+    }
+    if options.firmware_url_base.is_some() {
+        if !download::SUPPORTED {
+            err_n_die("--firmware-url-base is not available, go-modules was built without the download feature");
+        }
+        if let Err(err) =
+            download::check_url(options.firmware_url_base.as_deref().unwrap_or_default())
+        {
+            err_n_die(&format!("--firmware-url-base {}", err));
+        }
+        if options.embedded {
+            err_n_die("--firmware-url-base and --embedded can't be combined");
+        }
+    }
+    //keep stdout to only the json or summary line for the tools parsing it
+    if !options.json && !options.oneline && !options.print_mapping_json {
+        println!("GOcontroll module management utility V{}", VERSION);
+        #[cfg(debug_assertions)]
+        println!("Debug version");
+    }
     //get the controller hardware
-    let hardware_string= fs::read_to_string("/sys/firmware/devicetree/base/hardware").unwrap_or_else(|_|{
-		err_n_die("Could not find a hardware description file, this feature is not supported by your hardware.");
-	});
-
-    let controller = if hardware_string.contains("Moduline IV") {
-        ControllerTypes::ModulineIV
-    } else if hardware_string.contains("Moduline Mini") {
-        ControllerTypes::ModulineMini
-    } else if hardware_string.contains("Moduline Display") {
-        ControllerTypes::ModulineDisplay
+    let hardware_string = if transport::PRETEND {
+        transport::pretend_hardware()
+    } else {
+        fs::read_to_string("/sys/firmware/devicetree/base/hardware").unwrap_or_else(|_| {
+            err_n_die("Could not find a hardware description file, this feature is not supported by your hardware.");
+        })
+    };
+
+    //the raw string comes first, so a detection problem can be told apart from the string being unexpected
+    if options.controller_info {
+        print_controller_info(&hardware_string);
+    }
+    let controller_info = ControllerInfo::parse(&hardware_string).unwrap_or_else(|err| {
+        err_n_die(format!("{}. Can't proceed", err).as_str());
+    });
+    if options.verbose {
+        println!(
+            "Controller: {} revision {}",
+            controller_info.family,
+            controller_info.revision.as_deref().unwrap_or("unknown")
+        );
+    }
+    let controller = controller_info.family;
+    if let Err(err) = controller.check_slot_mapping() {
+        err_n_die(format!("{}, this build can't be used on this controller", err).as_str());
+    }
+
+    let config = Config::load().unwrap_or_else(|err| {
+        print_warning(format!("Warning: ignoring config, {}", err));
+        Config::default()
+    });
+    let filename_patterns = filename_patterns(&config);
+    let slot_labels_from_config = config
+        .slot_labels
+        .as_ref()
+        .is_some_and(|labels| labels.len() == controller.slot_count() as usize);
+    let slot_labels = match config.slot_labels {
+        Some(labels) if labels.len() == controller.slot_count() as usize => labels,
+        Some(labels) => {
+            print_warning(format!(
+                "Warning: ignoring slot_labels in {}, {} labels given but the controller has {} slots",
+                config::CONFIG_FILE,
+                labels.len(),
+                controller.slot_count()
+            ));
+            controller.default_slot_labels()
+        }
+        None => controller.default_slot_labels(),
+    };
+    _ = SLOT_LABELS.set(slot_labels);
+    let pins_given = !options.pin.is_empty();
+    let pins_in_config = !config.pinned_slots.is_empty();
+    options.pin.extend(config.pinned_slots);
+    let manage_services = options
+        .manage_services
+        .or(config.manage_services)
+        .unwrap_or(true);
+    for pin in &options.pin {
+        if parse_slot(pin).is_none_or(|slot| slot == 0 || slot > controller.slot_count()) {
+            print_warning(format!(
+                "Warning: ignoring pinned slot {}, no such slot",
+                pin
+            ));
+        }
+    }
+    let reset_path_template = options
+        .reset_path
+        .clone()
+        .or_else(|| env::var(RESET_PATH_ENV).ok())
+        .unwrap_or_else(|| DEFAULT_RESET_PATH.to_string());
+    if !reset_path_template.contains("{slot}") {
+        err_n_die(
+            format!(
+                "reset path {} has no {{slot}} to put the slot number in",
+                reset_path_template
+            )
+            .as_str(),
+        );
+    }
+    let reset_path_origin = if options.reset_path.is_some() {
+        "--reset-path"
+    } else if env::var(RESET_PATH_ENV).is_ok() {
+        RESET_PATH_ENV
     } else {
+        "default"
+    };
+    _ = RESET_PATH.set(reset_path_template);
+    _ = SPI_SPEED.set(options.spi_speed.unwrap_or(DEFAULT_SPI_SPEED));
+    let modules_file_name = options
+        .modules_file
+        .clone()
+        .or_else(|| env::var(MODULES_FILE_ENV).ok())
+        .unwrap_or_else(|| DEFAULT_MODULES_FILE_NAME.to_string());
+    //only the name is chosen, the file stays next to the rest of the module state
+    if modules_file_name.is_empty() || modules_file_name.contains('/') {
         err_n_die(
             format!(
-                "{} is not a supported GOcontroll Moduline product. Can't proceed",
-                hardware_string
+                "modules file name \"{}\" has to be a file name without a path",
+                modules_file_name
             )
             .as_str(),
         );
+    }
+    let modules_file_origin = if options.modules_file.is_some() {
+        "--modules-file"
+    } else if env::var(MODULES_FILE_ENV).is_ok() {
+        MODULES_FILE_ENV
+    } else {
+        "default"
     };
+    _ = MODULES_FILE_NAME.set(modules_file_name);
+    //everything that decides how the modules are driven is known here, before any module is touched
+    if args.get(1).map(String::as_str) == Some("config") {
+        let defaults = Options::default();
+        let flag_or_default =
+            |changed: bool, flag: &str| if changed { flag } else { "default" }.to_string();
+        let built_in = || "built in".to_string();
+        let hardware_origin = "/sys/firmware/devicetree/base/hardware".to_string();
+        let (firmware, firmware_origin) = if options.embedded {
+            ("embedded in the binary".to_string(), "--embedded")
+        } else if let Some(base) = &options.firmware_url_base {
+            (
+                format!("{}/index", base.trim_end_matches('/')),
+                "--firmware-url-base",
+            )
+        } else {
+            (FIRMWARE_DIR.to_string(), "default")
+        };
+        print_config(&[
+            (
+                "controller",
+                format!(
+                    "{} revision {}",
+                    controller,
+                    controller_info.revision.as_deref().unwrap_or("unknown")
+                ),
+                hardware_origin.clone(),
+            ),
+            (
+                "slots",
+                controller.slot_count().to_string(),
+                hardware_origin,
+            ),
+            (
+                "slot labels",
+                (1..=controller.slot_count())
+                    .map(slot_label)
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                flag_or_default(slot_labels_from_config, config::CONFIG_FILE),
+            ),
+            ("firmware", firmware, firmware_origin.to_string()),
+            (
+                "firmware patterns",
+                if config.firmware_patterns.is_empty() {
+                    "none".to_string()
+                } else {
+                    config.firmware_patterns.join(", ")
+                },
+                flag_or_default(!config.firmware_patterns.is_empty(), config::CONFIG_FILE),
+            ),
+            (
+                "modules file",
+                modules_file_path(),
+                modules_file_origin.to_string(),
+            ),
+            (
+                "reset path",
+                RESET_PATH.get().cloned().unwrap_or_default(),
+                reset_path_origin.to_string(),
+            ),
+            (
+                "spi speed",
+                format!("{} Hz", spi_speed()),
+                flag_or_default(options.spi_speed.is_some(), "--spi-speed"),
+            ),
+            (
+                "scan timeout",
+                format!("{} s", SCAN_TIMEOUT.as_secs()),
+                built_in(),
+            ),
+            ("wipe attempts", WIPE_ATTEMPTS.to_string(), built_in()),
+            (
+                "attempts",
+                options.attempts.to_string(),
+                flag_or_default(options.attempts != defaults.attempts, "--attempts"),
+            ),
+            (
+                "auto retry corrupted",
+                options.auto_retry_corrupted.to_string(),
+                flag_or_default(
+                    options.auto_retry_corrupted != defaults.auto_retry_corrupted,
+                    "--auto-retry-corrupted",
+                ),
+            ),
+            (
+                "inter module delay",
+                format!("{} ms", options.inter_module_delay.as_millis()),
+                flag_or_default(
+                    options.inter_module_delay != defaults.inter_module_delay,
+                    "--inter-module-delay",
+                ),
+            ),
+            (
+                "min free space",
+                format!("{} bytes", options.min_free_space),
+                flag_or_default(
+                    options.min_free_space != defaults.min_free_space,
+                    "--min-free-space",
+                ),
+            ),
+            (
+                "manage services",
+                manage_services.to_string(),
+                match (options.manage_services, config.manage_services) {
+                    (Some(true), _) => "--manage-services",
+                    (Some(false), _) => "--no-manage-services",
+                    (None, Some(_)) => config::CONFIG_FILE,
+                    (None, None) => "default",
+                }
+                .to_string(),
+            ),
+            (
+                "keep services",
+                options.keep_services.to_string(),
+                flag_or_default(options.keep_services, "--keep-services"),
+            ),
+            (
+                "pinned slots",
+                if options.pin.is_empty() {
+                    "none".to_string()
+                } else {
+                    options.pin.join(", ")
+                },
+                match (pins_given, pins_in_config) {
+                    (true, true) => format!("--pin and {}", config::CONFIG_FILE),
+                    (true, false) => "--pin".to_string(),
+                    (false, true) => config::CONFIG_FILE.to_string(),
+                    (false, false) => "default".to_string(),
+                },
+            ),
+            (
+                "report url",
+                options
+                    .report_url
+                    .clone()
+                    .unwrap_or_else(|| "none".to_string()),
+                flag_or_default(options.report_url.is_some(), "--report-url"),
+            ),
+        ]);
+        exit(0);
+    }
+    //for bring-up the mapping is useful even when the reset controls are missing, so it is printed before checking
+    if options.list_slots {
+        print_slot_mapping(&controller);
+        exit(0);
+    }
+    if options.print_mapping_json {
+        println!("{}", slot_mapping_json(&controller));
+        exit(0);
+    }
+    //without the reset control a module never enters its bootloader, so nothing would be found
+    //simulated modules need neither of these
+    let missing: Vec<String> = (1..=controller.slot_count())
+        .map(reset_path)
+        .filter(|path| !std::path::Path::new(path).exists())
+        .collect();
+    if !missing.is_empty() && !transport::PRETEND {
+        err_n_die(
+            format!(
+                "module reset control not found at {}, set the path with --reset-path or {}",
+                missing.join(", "),
+                RESET_PATH_ENV
+            )
+            .as_str(),
+        );
+    }
 
-    //stop services potentially trying to use the module
-    let output = Command::new("systemctl")
-        .arg("is-active")
-        .arg("nodered")
-        .output()
-        .unwrap()
-        .stdout;
-
-    let nodered = !String::from_utf8_lossy(&output).into_owned().contains("in");
+    //without these every module just fails to open or never resets, say why before anything is stopped
+    let denied = permission_denied(&controller);
+    if !denied.is_empty() && !transport::PRETEND {
+        err_n_die(
+            format!(
+                "no permission to access {}, run go-modules as root (sudo go-modules) or give this user read access to the module spidevs and write access to the reset controls",
+                denied.join(", ")
+            )
+            .as_str(),
+        );
+    }
 
-    let output = Command::new("systemctl")
-        .arg("is-active")
-        .arg("go-simulink")
-        .output()
-        .unwrap()
-        .stdout;
+    //stop services potentially trying to use the module, installations without them can leave systemctl alone
+    let service_active = |service: &str| {
+        let output = Command::new("systemctl")
+            .arg("is-active")
+            .arg(service)
+            .output()
+            .unwrap()
+            .stdout;
+        !String::from_utf8_lossy(&output).into_owned().contains("in")
+    };
+    let nodered = manage_services && service_active("nodered");
+    let simulink = manage_services && service_active("go-simulink");
 
-    let simulink = !String::from_utf8_lossy(&output).into_owned().contains("in");
+    //the services only get in the way when they use the module spi bus, an application that opens the bus
+    //later is caught by the check right before each wipe
+    let keep_services = options.keep_services && manage_services && {
+        let users = spidev_users("/dev/spidev");
+        if !users.is_empty() {
+            print_warning(format!(
+                "Warning: stopping the services anyway, the module spi bus is in use by {}",
+                users.join(", ")
+            ));
+        }
+        users.is_empty()
+    };
+    let (nodered, simulink) = if keep_services {
+        (false, false)
+    } else {
+        (nodered, simulink)
+    };
 
     if nodered {
         _ = Command::new("systemctl")
@@ -1267,131 +5503,482 @@ async fn main() {
             .status();
     }
 
-    match ctrlc::set_handler(move || err_n_restart_services(nodered, simulink)) {
+    //nothing might be feeding the watchdog with the services stopped
+    if let Some(path) = &options.watchdog_path {
+        if let Err(err) = watchdog::start(path, options.watchdog_interval) {
+            print_error(format!("Could not open the watchdog {}: {}", path, err));
+            err_n_restart_services(nodered, simulink);
+        }
+    }
+
+    //the first sigint cancels the uploads between frames so the modules are left in a known state, a second one exits
+    //right away
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancel_on_sigint = cancel.clone();
+    match ctrlc::set_handler(move || {
+        if cancel_on_sigint.swap(true, Ordering::Relaxed) {
+            save_completed(&controller);
+            err_n_restart_services(nodered, simulink);
+        }
+        print_warning("Cancelling, press ctrl-c again to stop immediately");
+    }) {
         Ok(()) => (),
         Err(err) => {
-            eprintln!("couldn't set sigint handler: {}", err);
+            print_error(format!("couldn't set sigint handler: {}", err));
+            err_n_restart_services(nodered, simulink);
+        }
+    }
+
+    //a reset doesn't need the module information, so it is done before the scan
+    if args.get(1).map(String::as_str) == Some("reset") {
+        if args.get(2).map(String::as_str) != Some("all") {
+            print_error("reset requires all");
+            print_usage();
+            err_n_restart_services(nodered, simulink);
+        }
+        let reset = reset_all_modules(controller).await;
+        if reset.len() != controller.slot_count() as usize {
+            print_error(format!(
+                "Reset {} of {} slots",
+                reset.len(),
+                controller.slot_count()
+            ));
             err_n_restart_services(nodered, simulink);
         }
+        println!("Reset all {} slots", reset.len());
+        success(nodered, simulink);
     }
 
+    //when a single slot is targeted on the command line only that slot is probed, so the other modules don't get reset
+    let target_slot = match args.get(1).map(String::as_str) {
+        Some("update") | Some("overwrite") | Some("benchmark") if options.qr.is_none() => {
+            args.get(2).and_then(|arg| parse_slot(arg))
+        }
+        _ => None,
+    };
+
+    //the scan below rewrites the modules file, keep what it was to report the changes
+    let previous_modules_file = fs::read_to_string(modules_file_path()).unwrap_or_default();
+
+    //create the base for the progress bar(s), an agent reports progress over stdout instead
+    let multi_progress = if options.agent {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    } else {
+        MultiProgress::new()
+    };
+    let style = progress_style();
+
+    //the scan spinner would draw over the command prompt and over the json or summary line of scan
+    let scan_progress = if args.get(1).is_none() || options.json || options.oneline {
+        ProgressBar::hidden()
+    } else {
+        let progress = multi_progress.add(ProgressBar::new(0).with_style(scan_progress_style()));
+        progress.enable_steady_tick(Duration::from_millis(100));
+        progress
+    };
+
     //start getting module information in a seperate task while other init is happening
-    let modules_fut = task::spawn(get_modules_and_save(controller));
+    let modules_fut = if let Some(slot) = target_slot {
+        task::spawn(async move { Module::new(slot, &controller).await.into_iter().collect() })
+    } else if options.no_save {
+        task::spawn(async move { get_modules(&controller, scan_progress).await })
+    } else {
+        task::spawn(get_modules_and_save(controller, scan_progress))
+    };
 
     //get all the firmwares
-    let available_firmwares: Vec<FirmwareVersion> = fs::read_dir("/lib/firmware/gocontroll/")
-        .unwrap_or_else(|_| {
-            eprintln!("Could not find the firmware folder");
-            err_n_restart_services(nodered, simulink);
-        }) // get the gocontroll firmware files
-        .map(|file| file.unwrap().file_name().to_str().unwrap().to_string()) //turn them into strings
-        .filter(|file_name| file_name.ends_with(".srec")) //keep only the srec files
-        .map(|firmware| FirmwareVersion::from_filename(firmware)) //turn them into FirmwareVersion Structs
-        .flatten()
-        .collect(); //collect them into a vector
-
-    //create the base for the progress bar(s)
-    let multi_progress = MultiProgress::new();
-    let style = ProgressStyle::with_template("{bar:40.cyan/blue} {pos:>7}/{len:7} ({eta}) {msg}")
-        .unwrap()
-        .progress_chars("##-")
-        .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
-            write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap()
-        });
+    let available_firmwares: Vec<FirmwareFile> = if options.embedded {
+        //only the firmware in the binary, the firmware folder doesn't have to exist
+        match FirmwareFile::embedded(&filename_patterns) {
+            Some(firmware) => vec![firmware],
+            None if embedded::FIRMWARE.is_none() => {
+                print_error("--embedded is not available, go-modules was built without the embedded feature");
+                err_n_restart_services(nodered, simulink);
+            }
+            None => {
+                print_error("The embedded firmware is not named like a firmware file");
+                err_n_restart_services(nodered, simulink);
+            }
+        }
+    } else if let Some(base) = &options.firmware_url_base {
+        let base = base.trim_end_matches('/');
+        download::list(base)
+            .unwrap_or_else(|err| {
+                print_error(format!("Error: {}", err));
+                err_n_restart_services(nodered, simulink);
+            })
+            .iter()
+            .filter(|file_name| file_name.ends_with(".srec"))
+            .filter_map(|file_name| {
+                FirmwareFile::remote(&format!("{}/{}", base, file_name), &filename_patterns)
+            })
+            .collect()
+    } else {
+        fs::read_dir(FIRMWARE_DIR)
+            .unwrap_or_else(|_| {
+                print_error("Could not find the firmware folder");
+                err_n_restart_services(nodered, simulink);
+            }) // get the gocontroll firmware files
+            .map(|file| file.unwrap().file_name().to_str().unwrap().to_string()) //turn them into strings
+            .filter(|file_name| file_name.ends_with(".srec")) //keep only the srec files
+            .filter_map(|file_name| FirmwareFile::from_filename(file_name, &filename_patterns)) //turn them into FirmwareFile Structs
+            .collect() //collect them into a vector
+    };
 
-    let command = if let Some(arg) = env::args().nth(1) {
+    let command = if let Some(arg) = args.get(1) {
         match arg.as_str() {
             "scan" => CommandArg::Scan,
             "update" => CommandArg::Update,
             "overwrite" => CommandArg::Overwrite,
+            "sync" => CommandArg::Sync,
+            "benchmark" => CommandArg::Benchmark,
+            "diff" => CommandArg::Diff,
             _ => {
-                eprintln!("Invalid command entered {}\n{}", arg, USAGE);
+                print_error(format!("Invalid command entered {}", arg));
+                print_usage();
                 err_n_restart_services(nodered, simulink);
             }
         }
     } else {
         Select::new(
             "What do you want to do?",
-            vec![CommandArg::Scan, CommandArg::Update, CommandArg::Overwrite],
+            vec![
+                CommandArg::Scan,
+                CommandArg::Update,
+                CommandArg::Overwrite,
+                CommandArg::Sync,
+                CommandArg::Benchmark,
+            ],
         )
         .prompt()
         .unwrap_or_else(|_| err_n_restart_services(nodered, simulink))
     };
 
+    //the other commands are about changing the modules, the file has to follow them
+    if options.no_save && !matches!(command, CommandArg::Scan) {
+        print_error(format!(
+            "Error: --no-save only applies to scan, not {}",
+            command
+        ));
+        err_n_restart_services(nodered, simulink);
+    }
+
+    //make sure the result of a flash can be saved before anything gets wiped
+    if matches!(command, CommandArg::Update | CommandArg::Overwrite) {
+        //the bootloader only has the type 29 wipe, it has no way to keep a backup image to roll back to
+        if options.safe {
+            print_warning("Warning: --safe has no effect, the module bootloader can't keep the old firmware, it is still wiped before uploading");
+        }
+        //the bootloader can't report a crc of its flash, so there is nothing to compare the firmware with
+        if options.delta {
+            print_warning("Warning: --delta has no effect, the module bootloader can't report the contents of its flash, the full firmware is uploaded");
+        }
+        if let Err(err) = check_free_space(options.min_free_space) {
+            if options.strict {
+                print_error(format!("Error: {}", err));
+                err_n_restart_services(nodered, simulink);
+            }
+            print_warning(format!("Warning: {}", err));
+        }
+    }
+
     //get the modules from the previously started task
     let modules = modules_fut.await.unwrap_or_else(|_| {
-        eprintln!("Could not get module information");
+        print_error("Could not get module information");
         err_n_restart_services(nodered, simulink);
     });
+    //only a full scan saved the state of every slot, a single probed slot says nothing about the others
+    if let (Some(path), None) = (&options.history, target_slot) {
+        let modules_file = if options.no_save {
+            scanned_modules_file(&modules, &controller)
+        } else {
+            fs::read_to_string(modules_file_path()).unwrap_or_default()
+        };
+        append_history(path, &modules_file, &extra_info(&modules));
+    }
+    //cancelled while scanning, before any module got touched
+    if cancel.load(Ordering::Relaxed) {
+        err_n_restart_services(nodered, simulink);
+    }
+    //only update restores wiped modules, an explicit overwrite flashes the module anyway and the other commands
+    //never change a module
+    if !matches!(command, CommandArg::Update | CommandArg::Overwrite) {
+        for module in &modules {
+            if module.firmware.get_software_version() == SoftwareVersion::BLANK {
+                print_warning(format!(
+                    "Warning: slot {} has no firmware, probably from an interrupted upload, update restores its last known firmware",
+                    slot_label(module.slot)
+                ));
+            }
+        }
+    }
 
     match command {
         CommandArg::Scan => {
             //scan and save has already been done before this option was even selected, print out the values and exit
-            if !modules.is_empty() {
+            if options.json && options.no_save {
+                println!(
+                    "{}",
+                    modules_file_json(
+                        &scanned_modules_file(&modules, &controller),
+                        &[],
+                        &extra_info(&modules),
+                        options.qr_side,
+                        None,
+                    )
+                );
+            } else if options.json {
+                //the modules file was just rewritten by the scan, so it holds exactly what was found
+                println!(
+                    "{}",
+                    modules_file_json(
+                        &fs::read_to_string(modules_file_path()).unwrap_or_default(),
+                        &[],
+                        &extra_info(&modules),
+                        options.qr_side,
+                        None,
+                    )
+                );
+            } else if options.oneline {
+                println!("{}", scan_oneline(&modules, &controller));
+            } else if !modules.is_empty() && options.table {
+                print_module_table(&modules, options.qr_side);
+            } else if !modules.is_empty() {
                 println!("Found modules:");
                 for module in &modules {
                     println!("{}", module);
+                    if let Some(hex) = module.extra_info_hex() {
+                        println!("    extra info: {}", hex);
+                    }
+                    if options.verbose {
+                        println!(
+                            "    bootloader protocol {}, {} checksum",
+                            module.boot_protocol,
+                            module.checksum.name()
+                        );
+                    }
                 }
             } else {
                 println!("No modules found")
             }
+            print_stuck_slots(&previous_modules_file, options.verbose);
+            //for manufacturing tests, where a controller has to hold a known number of modules
+            if options.fail_if_empty && modules.is_empty() {
+                print_error("Error: no modules found");
+                err_n_restart_services(nodered, simulink);
+            }
+            if let Some(expected) = options.expect_modules {
+                if modules.len() != expected {
+                    print_error(format!(
+                        "Error: expected {} modules but found {}",
+                        expected,
+                        modules.len()
+                    ));
+                    err_n_restart_services(nodered, simulink);
+                }
+            }
+            success(nodered, simulink);
+        }
+
+        CommandArg::Sync => {
+            //rewrite the file from scratch instead of merging into what was there, only the slots the scan missed keep
+            //their entries
+            let reset = keep_missed_columns(
+                &controller.get_empty_modules_file(),
+                &fs::read_to_string(modules_file_path()).unwrap_or_default(),
+                &missed_slots(),
+            );
+            if fs::write(modules_file_path(), reset).is_err() {
+                print_error(format!("Could not reset {}", modules_file_path()));
+                err_n_restart_services(nodered, simulink);
+            }
+            save_modules(modules_by_slot(modules, &controller), &controller);
+            println!(
+                "Synchronized {} with the modules in the controller:",
+                modules_file_path()
+            );
+            print_modules_file_diff(
+                &previous_modules_file,
+                &fs::read_to_string(modules_file_path()).unwrap_or_default(),
+            );
+            success(nodered, simulink);
+        }
+
+        CommandArg::Diff => {
+            //the path of the other file has to come from the command line, diff isn't offered in the tui
+            let other = args.get(2).unwrap_or_else(|| {
+                print_error("diff requires the modules file of the other controller");
+                print_usage();
+                err_n_restart_services(nodered, simulink);
+            });
+            let other_modules_file = fs::read_to_string(other).unwrap_or_else(|err| {
+                print_error(format!("Could not read {}: {}", other, err));
+                err_n_restart_services(nodered, simulink);
+            });
+            print_inventory_diff(&modules_by_slot(modules, &controller), &other_modules_file);
+            success(nodered, simulink);
+        }
+
+        CommandArg::Benchmark => {
+            let mut module = if let Some(arg) = args.get(2) {
+                let slot = parse_slot(arg).unwrap_or_else(|| {
+                    print_error("Invalid slot entered");
+                    print_usage();
+                    err_n_restart_services(nodered, simulink);
+                });
+                modules
+                    .into_iter()
+                    .find(|module| module.slot == slot)
+                    .unwrap_or_else(|| {
+                        print_error(format!(
+                            "Couldn't find a module in slot {}",
+                            slot_label(slot)
+                        ));
+                        err_n_restart_services(nodered, simulink);
+                    })
+            } else if !modules.is_empty() {
+                Select::new(SLOT_PROMPT, modules)
+                    .with_page_size(8)
+                    .prompt()
+                    .unwrap_or_else(|_| err_n_restart_services(nodered, simulink))
+            } else {
+                print_error("No modules found in the controller.");
+                err_n_restart_services(nodered, simulink);
+            };
+            match module.benchmark().await {
+                Some(speed) if speed < spi_speed() => print_warning(format!(
+                    "Highest reliable spi speed for slot {}: {} Hz, below the {} Hz in use, lower it with --spi-speed",
+                    slot_label(module.slot),
+                    speed,
+                    spi_speed()
+                )),
+                Some(speed) => print_success(format!(
+                    "Highest reliable spi speed for slot {}: {} Hz, {} Hz is in use",
+                    slot_label(module.slot),
+                    speed,
+                    spi_speed()
+                )),
+                None => print_warning(format!(
+                    "No reliable spi speed found for slot {}",
+                    slot_label(module.slot)
+                )),
+            }
             success(nodered, simulink);
         }
 
         CommandArg::Update => {
+            let permit = WipePermit::for_update();
+            //an update with --no-save is an audit that must not change any module
+            let modules = if options.no_save {
+                modules
+            } else {
+                recover_wiped_modules(
+                    modules,
+                    &permit,
+                    &previous_modules_file,
+                    &available_firmwares,
+                    &multi_progress,
+                    &style,
+                    &options,
+                    &controller,
+                    &cancel,
+                )
+                .await
+            };
             //find the update type
-            if let Some(arg) = env::args().nth(2) {
+            if let Some(arg) = args.get(2) {
                 match arg.as_str() {
                     "all" => {
                         update_all_modules(
                             modules,
+                            permit,
                             &available_firmwares,
                             &multi_progress,
                             &style,
+                            &options,
                             controller,
+                            &cancel,
                             nodered,
                             simulink,
                         )
                         .await
                     }
                     _ => {
-                        if let Ok(slot) = arg.parse::<u8>() {
+                        if let Some(slot) = parse_slot(arg) {
                             let module = modules
                                 .into_iter()
                                 .find(|module| module.slot == slot)
-                                .take()
                                 .unwrap_or_else(|| {
-                                    eprintln!("Couldn't find a module in slot {}", slot);
+                                    print_error(format!(
+                                        "Couldn't find a module in slot {}",
+                                        slot_label(slot)
+                                    ));
                                     err_n_restart_services(nodered, simulink);
                                 });
                             update_one_module(
                                 module,
+                                permit,
                                 &available_firmwares,
                                 multi_progress,
                                 style,
+                                &options,
                                 controller,
+                                &cancel,
                                 nodered,
                                 simulink,
                             )
                             .await;
                         } else {
-                            eprintln!("{}", USAGE);
+                            print_usage();
                             err_n_restart_services(nodered, simulink);
                         }
                     }
                 }
             } else {
-                match Select::new("Update one module or all?", vec!["all", "one"])
-                    .prompt()
-                    .unwrap_or_else(|_| err_n_restart_services(nodered, simulink))
+                match Select::new(
+                    "Update one module, selected modules or all?",
+                    vec!["all", "selected", "one"],
+                )
+                .prompt()
+                .unwrap_or_else(|_| err_n_restart_services(nodered, simulink))
                 {
                     "all" => {
                         update_all_modules(
                             modules,
+                            permit,
+                            &available_firmwares,
+                            &multi_progress,
+                            &style,
+                            &options,
+                            controller,
+                            &cancel,
+                            nodered,
+                            simulink,
+                        )
+                        .await
+                    }
+                    "selected" => {
+                        if modules.is_empty() {
+                            print_error("No modules found in the controller.");
+                            err_n_restart_services(nodered, simulink);
+                        }
+                        let selected = MultiSelect::new("select the modules to update", modules)
+                            .with_page_size(8)
+                            .prompt()
+                            .unwrap_or_else(|_| err_n_restart_services(nodered, simulink));
+                        if selected.is_empty() {
+                            print_warning("No modules selected.");
+                            success(nodered, simulink);
+                        }
+                        update_all_modules(
+                            selected,
+                            permit,
                             &available_firmwares,
                             &multi_progress,
                             &style,
+                            &options,
                             controller,
+                            &cancel,
                             nodered,
                             simulink,
                         )
@@ -1406,10 +5993,13 @@ async fn main() {
                                 Ok(module) => {
                                     update_one_module(
                                         module,
+                                        permit,
                                         &available_firmwares,
                                         multi_progress,
                                         style,
+                                        &options,
                                         controller,
+                                        &cancel,
                                         nodered,
                                         simulink,
                                     )
@@ -1420,12 +6010,12 @@ async fn main() {
                                 }
                             }
                         } else {
-                            eprintln!("No modules found in the controller.");
+                            print_error("No modules found in the controller.");
                             err_n_restart_services(nodered, simulink);
                         }
                     }
                     _ => {
-                        eprintln!("You shouldn't be here, turn back to whence you came");
+                        print_error("You shouldn't be here, turn back to whence you came");
                         err_n_restart_services(nodered, simulink);
                     }
                 }
@@ -1433,18 +6023,88 @@ async fn main() {
         }
 
         CommandArg::Overwrite => {
-            let mut module = if let Some(arg) = env::args().nth(2) {
-                if let Ok(slot) = arg.parse::<u8>() {
+            let permit = WipePermit::for_overwrite();
+            if let Some(path) = &options.from_csv {
+                overwrite_from_csv(
+                    path,
+                    permit,
+                    modules,
+                    &available_firmwares,
+                    &multi_progress,
+                    &style,
+                    &options,
+                    controller,
+                    &cancel,
+                    nodered,
+                    simulink,
+                )
+                .await;
+            }
+            if let Some(module_type) = &options.module_type {
+                let Some(version) = options.to else {
+                    print_error(
+                        "overwrite --type requires the software version to flash with --to",
+                    );
+                    err_n_restart_services(nodered, simulink);
+                };
+                overwrite_type(
+                    module_type,
+                    version,
+                    permit,
+                    modules,
+                    &available_firmwares,
+                    &multi_progress,
+                    &style,
+                    &options,
+                    controller,
+                    &cancel,
+                    nodered,
+                    simulink,
+                )
+                .await;
+            }
+            let mut module = if let Some(qr) = options.qr {
+                let mut matches: Vec<Module> = modules
+                    .into_iter()
+                    .filter(|module| options.qr_side.matches(module, qr))
+                    .collect();
+                match matches.len() {
+                    0 => {
+                        print_error(format!(
+                            "Couldn't find a module with {} code {}",
+                            options.qr_side, qr
+                        ));
+                        err_n_restart_services(nodered, simulink);
+                    }
+                    1 => matches.pop().unwrap(),
+                    _ => {
+                        print_error(format!(
+                            "QR code {} matches multiple modules, in slots {}",
+                            qr,
+                            matches
+                                .iter()
+                                .map(|module| slot_label(module.slot))
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        ));
+                        err_n_restart_services(nodered, simulink);
+                    }
+                }
+            } else if let Some(arg) = args.get(2) {
+                if let Some(slot) = parse_slot(arg) {
                     modules
                         .into_iter()
                         .find(|module| module.slot == slot)
-                        .take()
                         .unwrap_or_else(|| {
-                            eprintln!("Couldn't find a module in slot {}", slot);
+                            print_error(format!(
+                                "Couldn't find a module in slot {}",
+                                slot_label(slot)
+                            ));
                             err_n_restart_services(nodered, simulink);
                         })
                 } else {
-                    eprintln!("Invalid slot entered\n{}", USAGE);
+                    print_error("Invalid slot entered");
+                    print_usage();
                     err_n_restart_services(nodered, simulink);
                 }
             } else if !modules.is_empty() {
@@ -1453,65 +6113,139 @@ async fn main() {
                     .prompt()
                     .unwrap_or_else(|_| err_n_restart_services(nodered, simulink))
             } else {
-                eprintln!("No modules found in the controller.");
+                print_error("No modules found in the controller.");
                 err_n_restart_services(nodered, simulink);
             };
 
-            let new_firmware = if let Some(arg) = env::args().nth(3) {
-                if let Some(firmware) = FirmwareVersion::from_filename(arg.clone()) {
-                    if available_firmwares.contains(&firmware) {
-                        firmware
-                    } else {
-                        eprintln!("/lib/firmware/gocontroll/{} does not exist", arg);
+            if options.is_pinned(module.slot) && !options.force {
+                print_error(format!(
+                    "slot {} is pinned, use --force to overwrite it anyway",
+                    slot_label(module.slot)
+                ));
+                err_n_restart_services(nodered, simulink);
+            }
+
+            //with --qr the slot argument is left out, so the firmware moves up one place
+            let firmware_arg = args.get(if options.qr.is_some() { 2 } else { 3 });
+            let new_firmware = if let Some(arg) = firmware_arg {
+                let firmware = if download::is_url(arg) {
+                    if !download::SUPPORTED {
+                        print_error("Flashing firmware from a url is not available, go-modules was built without the download feature");
+                        err_n_restart_services(nodered, simulink);
+                    }
+                    if let Err(err) = download::check_url(arg) {
+                        print_error(format!("Error: {}", err));
                         err_n_restart_services(nodered, simulink);
                     }
+                    FirmwareFile::remote(arg, &filename_patterns).unwrap_or_else(|| {
+                        print_error(format!(
+                            "Invalid firmware entered, {} is not named like a firmware file",
+                            download::basename(arg)
+                        ));
+                        err_n_restart_services(nodered, simulink);
+                    })
                 } else {
-                    eprintln!("Invalid firmware entered\n{}", USAGE);
-                    err_n_restart_services(nodered, simulink);
+                    find_firmware(arg, &available_firmwares).unwrap_or_else(|err| {
+                        print_error(err);
+                        print_usage();
+                        err_n_restart_services(nodered, simulink);
+                    })
+                };
+                //the same check the firmware selection below filters on, the module is wiped before anything is verified
+                if !module.firmware.is_factory_blank()
+                    && firmware.version.get_hardware() != module.firmware.get_hardware()
+                {
+                    if !options.force {
+                        print_error(format!(
+                            "{} is not made for the module in slot {} with firmware {}, use --force to overwrite it anyway",
+                            firmware,
+                            slot_label(module.slot),
+                            module.firmware.as_string()
+                        ));
+                        err_n_restart_services(nodered, simulink);
+                    }
+                    print_warning(format!(
+                        "Warning: flashing {} made for other hardware into slot {}",
+                        firmware,
+                        slot_label(module.slot)
+                    ));
                 }
+                firmware
             } else {
-                let valid_firmwares: Vec<&FirmwareVersion> = available_firmwares
+                //a factory blank module doesn't know its hardware yet, so any firmware could be the right one
+                let valid_firmwares: Vec<&FirmwareFile> = available_firmwares
                     .iter()
-                    .filter(|firmware| firmware.get_hardware() == module.firmware.get_hardware())
+                    .filter(|firmware| {
+                        module.firmware.is_factory_blank()
+                            || firmware.version.get_hardware() == module.firmware.get_hardware()
+                    })
                     .collect();
                 if !valid_firmwares.is_empty() {
-                    *Select::new("Which firmware to upload?", valid_firmwares)
+                    Select::new("Which firmware to upload?", valid_firmwares)
                         .prompt()
                         .unwrap_or_else(|_| err_n_restart_services(nodered, simulink))
+                        .clone()
                 } else {
-                    eprintln!("No firmware(s) found for this module.");
+                    print_error("No firmware(s) found for this module.");
                     err_n_restart_services(nodered, simulink);
                 }
             };
             match module
-                .overwrite_module(&new_firmware, multi_progress, style)
+                .overwrite_module_retrying(
+                    &permit,
+                    &new_firmware,
+                    multi_progress,
+                    style,
+                    &options,
+                    &cancel,
+                )
                 .await
             {
                 Ok(()) => {
-                    println!(
+                    print_success(format!(
                         "succesfully updated slot {} from {} to {}",
-                        module.slot,
+                        slot_label(module.slot),
                         module.firmware.as_string(),
-                        new_firmware.as_string()
-                    );
-                    module.firmware = new_firmware;
+                        new_firmware.version.as_string()
+                    ));
+                    module.firmware = new_firmware.version;
                     save_modules(vec![Some(module)], &controller);
                     success(nodered, simulink);
                 }
                 Err(err) => match err {
                     UploadError::FirmwareCorrupted(slot) => {
-                        eprintln!(
+                        print_error(format!(
                             "firmware upload critically failed on slot {}, wiping firmware...",
-                            slot
-                        );
-                        module.wipe_module_error().await;
-                        err_n_die(
-                            format!("Update failed, firmware is corrupted on slot {}", slot)
-                                .as_str(),
+                            slot_label(slot)
+                        ));
+                        module.wipe_module_error(&permit).await;
+                        print_upload_error(
+                            &err,
+                            format!(
+                                "Update failed, firmware is corrupted on slot {}",
+                                slot_label(slot)
+                            ),
                         );
+                        err_n_die("could not restart nodered and go-simulink services due to corrupted firmware.");
                     }
                     UploadError::FirmwareUntouched(slot) => {
-                        eprintln!("Update failed on slot {}", slot);
+                        print_upload_error(
+                            &err,
+                            format!("Update failed on slot {}", slot_label(slot)),
+                        );
+                        err_n_restart_services(nodered, simulink);
+                    }
+                    UploadError::Recovered(slot, recovery) => {
+                        print_upload_error(&err, recovered_message(slot, recovery));
+                        module.firmware = recovery;
+                        save_modules(vec![Some(module)], &controller);
+                        err_n_restart_services(nodered, simulink);
+                    }
+                    UploadError::Cancelled(slot) => {
+                        print_upload_error(
+                            &err,
+                            format!("Update cancelled on slot {}", slot_label(slot)),
+                        );
                         err_n_restart_services(nodered, simulink);
                     }
                 },
@@ -1519,3 +6253,509 @@ async fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::{mock, simulated::Bootloader};
+
+    /// an srec record with its byte count and checksum
+    fn srec_record(kind: char, address: &[u8], data: &[u8]) -> String {
+        let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+        bytes.extend(address);
+        bytes.extend(data);
+        let checksum = !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        bytes.push(checksum);
+        let hex: String = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        format!("S{}{}", kind, hex)
+    }
+
+    /// a firmware file with a header, data_records S3 records of 16 bytes and the S7 record that ends the upload
+    fn test_firmware(data_records: u32) -> FirmwareFile {
+        let mut lines = vec![srec_record('0', &[0, 0], b"HDR")];
+        for record in 0..data_records {
+            let data: Vec<u8> = (0..16).map(|byte| (record * 16 + byte) as u8).collect();
+            lines.push(srec_record('3', &(record * 16).to_be_bytes(), &data));
+        }
+        lines.push(srec_record('7', &[0, 0, 0, 0], &[]));
+        FirmwareFile {
+            version: FirmwareVersion {
+                firmware: [20, 10, 1, 5, 0, 1, 0],
+            },
+            filename: "20-10-1-5-0-1-0.srec".to_string(),
+            source: FirmwareSource::Embedded(Box::leak(lines.join("\n").into_boxed_str())),
+        }
+    }
+
+    /// a module in slot 1 on the mock spidev at path, already in its bootloader like after a scan
+    fn mock_module(path: &str, firmware: [u8; 7]) -> Module {
+        Module {
+            slot: 1,
+            spidev: Spi::open(path).unwrap(),
+            interrupt: None,
+            checksum: Checksum::WrappingAdd,
+            boot_protocol: 0,
+            extra_info: Vec::new(),
+            firmware: FirmwareVersion { firmware },
+            manufacturer: 0,
+            qr_front: 1,
+            qr_back: 2,
+            wipe_time: None,
+        }
+    }
+
+    /// let a simulated bootloader with this firmware answer on the mock spidev at path
+    fn script_bootloader(path: &str, firmware: [u8; 7]) -> Arc<Mutex<Bootloader>> {
+        let bootloader = Arc::new(Mutex::new(Bootloader::new(firmware, 0, 1, 2)));
+        let module_side = bootloader.clone();
+        mock::script(path, move |tx_buf, rx_buf| {
+            let mut bootloader = module_side.lock().unwrap();
+            if rx_buf.is_empty() {
+                bootloader.receive(tx_buf);
+            } else {
+                bootloader.transfer(tx_buf, rx_buf);
+            }
+            Ok(())
+        });
+        bootloader
+    }
+
+    async fn upload(module: &mut Module, firmware: &FirmwareFile) -> Result<(), UploadError> {
+        module
+            .overwrite_module(
+                &WipePermit::for_overwrite(),
+                firmware,
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+                ProgressStyle::default_bar(),
+                &Options::default(),
+                &AtomicBool::new(false),
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn upload_sends_every_data_record_and_sets_the_new_version() {
+        let bootloader = script_bootloader("mock-upload", [20, 10, 1, 5, 0, 0, 9]);
+        let mut module = mock_module("mock-upload", [20, 10, 1, 5, 0, 0, 9]);
+
+        assert!(upload(&mut module, &test_firmware(8)).await.is_ok());
+        let bootloader = bootloader.lock().unwrap();
+        assert_eq!(bootloader.firmware, [20, 10, 1, 5, 0, 1, 0]);
+        //the header, the 8 data records and the S7 record that starts the firmware
+        assert_eq!(bootloader.lines_received, 10);
+        //without an interrupt the end of the wipe isn't seen, so its time is unknown
+        assert_eq!(module.wipe_time, None);
+    }
+
+    #[test]
+    fn the_wipe_time_is_only_shown_when_it_was_measured() {
+        assert_eq!(
+            describe_wipe(Some(Duration::from_millis(850))),
+            "wiped in 850 ms"
+        );
+        assert_eq!(describe_wipe(None), "wipe time unknown in timed mode");
+    }
+
+    #[test]
+    fn firmware_versions_round_trip_through_their_filename() {
+        let versions = [
+            [0; 7],
+            [255; 7],
+            [20, 10, 1, 5, 0, 0, 9],
+            [20, 20, 2, 5, 255, 255, 255],
+            [1, 2, 3, 4, 5, 6, 7],
+        ];
+        //and a spread of other versions, every byte value appears in each position
+        let spread = (0..=255u8).map(|i| {
+            [
+                i,
+                i.wrapping_mul(3),
+                i.wrapping_add(7),
+                255 - i,
+                i.wrapping_mul(31),
+                i / 2,
+                i.rotate_left(4),
+            ]
+        });
+        for firmware in versions.into_iter().chain(spread) {
+            let version = FirmwareVersion { firmware };
+            assert_eq!(
+                FirmwareVersion::from_filename(version.as_filename()),
+                Some(version)
+            );
+        }
+    }
+
+    #[test]
+    fn firmware_version_strings_are_seven_numbers_joined_by_dashes() {
+        let version = FirmwareVersion {
+            firmware: [20, 10, 1, 5, 0, 0, 9],
+        };
+        assert_eq!(version.as_string(), "20-10-1-5-0-0-9");
+        assert_eq!(version.as_filename(), "20-10-1-5-0-0-9.srec");
+        let version = FirmwareVersion {
+            firmware: [255, 0, 255, 0, 255, 0, 255],
+        };
+        assert_eq!(version.as_string(), "255-0-255-0-255-0-255");
+    }
+
+    #[tokio::test]
+    async fn a_short_read_mid_upload_is_retried() {
+        let bootloader = Arc::new(Mutex::new(Bootloader::new(
+            [20, 10, 1, 5, 0, 0, 9],
+            0,
+            1,
+            2,
+        )));
+        let module_side = bootloader.clone();
+        let short_reads = Arc::new(Mutex::new(0));
+        let counted = short_reads.clone();
+        mock::script("mock-short-read", move |tx_buf, rx_buf| {
+            let mut bootloader = module_side.lock().unwrap();
+            if rx_buf.is_empty() {
+                bootloader.receive(tx_buf);
+                return Ok(());
+            }
+            bootloader.transfer(tx_buf, rx_buf);
+            //the answer to the fourth firmware line comes back cut off once, the rest stays 0xFF from the prefill
+            let mut short_reads = counted.lock().unwrap();
+            if bootloader.lines_received == 5 && *short_reads == 0 {
+                *short_reads += 1;
+                rx_buf[10..].fill(0xFF);
+            }
+            Ok(())
+        });
+        let mut module = mock_module("mock-short-read", [20, 10, 1, 5, 0, 0, 9]);
+
+        assert!(upload(&mut module, &test_firmware(8)).await.is_ok());
+        assert_eq!(*short_reads.lock().unwrap(), 1);
+        let bootloader = bootloader.lock().unwrap();
+        assert_eq!(bootloader.firmware, [20, 10, 1, 5, 0, 1, 0]);
+        //the line whose answer was cut off is sent once more
+        assert_eq!(bootloader.lines_received, 11);
+    }
+
+    #[test]
+    fn only_wiped_software_is_blank() {
+        assert!(SoftwareVersion::BLANK.is_blank());
+        assert!(!version(0, 0, 0).is_blank());
+        assert!(!version(0, 0, 1).is_blank());
+        assert!(!version(255, 255, 254).is_blank());
+    }
+
+    #[test]
+    fn only_an_all_zero_version_is_factory_blank() {
+        assert!(FirmwareVersion { firmware: [0; 7] }.is_factory_blank());
+        //wiped, its hardware is still known
+        assert!(!FirmwareVersion {
+            firmware: [20, 10, 1, 5, 255, 255, 255]
+        }
+        .is_factory_blank());
+        assert!(!FirmwareVersion {
+            firmware: [20, 10, 1, 5, 0, 0, 0]
+        }
+        .is_factory_blank());
+    }
+
+    async fn update(module: Module, firmware: &FirmwareFile) -> Result<bool, UploadError> {
+        module
+            .update_module(
+                &WipePermit::for_update(),
+                std::slice::from_ref(firmware),
+                MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+                ProgressStyle::default_bar(),
+                &Options::default(),
+                &AtomicBool::new(false),
+            )
+            .await
+            .map(|updated| updated.is_ok())
+    }
+
+    #[tokio::test]
+    async fn update_programs_a_wiped_module_even_with_a_staged_0_0_0() {
+        let wiped = [20, 10, 1, 5, 255, 255, 255];
+        let bootloader = script_bootloader("mock-update-wiped", wiped);
+        let mut firmware = test_firmware(2);
+        firmware.version.firmware = [20, 10, 1, 5, 0, 0, 0];
+        assert!(matches!(
+            update(mock_module("mock-update-wiped", wiped), &firmware).await,
+            Ok(true)
+        ));
+        assert_eq!(bootloader.lock().unwrap().firmware, [20, 10, 1, 5, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn update_leaves_a_module_running_0_0_0_alone_when_it_is_staged() {
+        let frames = Arc::new(Mutex::new(0));
+        let counted = frames.clone();
+        mock::script("mock-update-0-0-0", move |_, _| {
+            *counted.lock().unwrap() += 1;
+            Ok(())
+        });
+        let mut firmware = test_firmware(2);
+        firmware.version.firmware = [20, 10, 1, 5, 0, 0, 0];
+        assert!(matches!(
+            update(
+                mock_module("mock-update-0-0-0", [20, 10, 1, 5, 0, 0, 0]),
+                &firmware
+            )
+            .await,
+            Ok(false)
+        ));
+        assert_eq!(*frames.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn update_leaves_a_factory_blank_module_alone() {
+        let frames = Arc::new(Mutex::new(0));
+        let counted = frames.clone();
+        mock::script("mock-update-factory-blank", move |_, _| {
+            *counted.lock().unwrap() += 1;
+            Ok(())
+        });
+        assert!(matches!(
+            update(
+                mock_module("mock-update-factory-blank", [0; 7]),
+                &test_firmware(2)
+            )
+            .await,
+            Ok(false)
+        ));
+        assert_eq!(*frames.lock().unwrap(), 0);
+    }
+
+    fn version(major: u8, minor: u8, patch: u8) -> SoftwareVersion {
+        SoftwareVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn software_versions_compare_by_number_not_by_text() {
+        assert!(version(1, 0, 10) < version(1, 1, 0));
+        assert!(version(1, 0, 9) < version(1, 0, 10));
+        assert!(version(0, 255, 255) < version(1, 0, 0));
+        assert!(version(2, 0, 0) > version(1, 255, 255));
+    }
+
+    #[test]
+    fn equal_software_versions_are_equal() {
+        assert_eq!(
+            version(1, 2, 3).cmp(&version(1, 2, 3)),
+            std::cmp::Ordering::Equal
+        );
+        assert_eq!(version(1, 2, 3), version(1, 2, 3));
+    }
+
+    #[test]
+    fn the_highest_software_version_is_the_largest() {
+        assert!(version(255, 255, 254) < version(255, 255, 255));
+        assert_eq!(
+            [version(255, 255, 255), version(0, 0, 1), version(5, 0, 9)]
+                .into_iter()
+                .max(),
+            Some(version(255, 255, 255))
+        );
+    }
+
+    #[test]
+    fn srec_with_only_a_header_and_terminator_is_refused() {
+        let lines = ["S00600004844521B", "S70500000000FA"];
+        assert_eq!(
+            check_srec(&lines),
+            Err("it has no data records".to_string())
+        );
+    }
+
+    /// the mapping of every slot as the tool had it before the tables, spidev, gpiochip and line in slot order
+    fn mapping_of(controller: ControllerTypes) -> Vec<(&'static str, &'static str, u32)> {
+        controller
+            .slot_mapping()
+            .iter()
+            .map(|slot| (slot.spidev, slot.gpiochip, slot.line))
+            .collect()
+    }
+
+    #[test]
+    fn the_slot_mappings_match_the_wiring() {
+        assert_eq!(
+            mapping_of(ControllerTypes::ModulineIV),
+            [
+                ("/dev/spidev1.0", "/dev/gpiochip0", 6),
+                ("/dev/spidev1.1", "/dev/gpiochip4", 20),
+                ("/dev/spidev2.0", "/dev/gpiochip0", 7),
+                ("/dev/spidev2.1", "/dev/gpiochip4", 21),
+                ("/dev/spidev2.2", "/dev/gpiochip4", 1),
+                ("/dev/spidev2.3", "/dev/gpiochip3", 26),
+                ("/dev/spidev0.0", "/dev/gpiochip2", 19),
+                ("/dev/spidev0.1", "/dev/gpiochip2", 22),
+            ]
+        );
+        assert_eq!(
+            mapping_of(ControllerTypes::ModulineMini),
+            [
+                ("/dev/spidev1.0", "/dev/gpiochip0", 10),
+                ("/dev/spidev1.1", "/dev/gpiochip0", 5),
+                ("/dev/spidev2.0", "/dev/gpiochip3", 26),
+                ("/dev/spidev2.1", "/dev/gpiochip2", 19),
+            ]
+        );
+        assert_eq!(
+            mapping_of(ControllerTypes::ModulineDisplay),
+            [
+                ("/dev/spidev1.0", "/dev/gpiochip3", 5),
+                ("/dev/spidev1.1", "/dev/gpiochip0", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn every_slot_mapping_passes_its_check() {
+        for controller in [
+            ControllerTypes::ModulineIV,
+            ControllerTypes::ModulineMini,
+            ControllerTypes::ModulineDisplay,
+        ] {
+            assert_eq!(controller.check_slot_mapping(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn a_missed_slot_keeps_its_column() {
+        let previous = "20-10-1-5-0-0-9:20-20-1-5-0-0-3\n20:20\n111:222\n1:2";
+        let scanned = "20-10-1-5-0-1-0:\n20:\n333:\n3:";
+        assert_eq!(
+            keep_missed_columns(scanned, previous, &[2]),
+            "20-10-1-5-0-1-0:20-20-1-5-0-0-3\n20:20\n333:222\n3:2"
+        );
+        assert_eq!(keep_missed_columns(scanned, previous, &[]), scanned);
+    }
+
+    #[test]
+    fn only_plain_http_urls_are_downloaded() {
+        assert_eq!(download::check_url("http://provisioning/firmware"), Ok(()));
+        assert!(download::check_url("https://provisioning/firmware").is_err());
+        assert!(download::check_url("ftp://provisioning/firmware").is_err());
+    }
+
+    #[test]
+    fn wrapping_add_sums_the_first_length_bytes() {
+        assert_eq!(
+            Checksum::WrappingAdd.calculate(&[0x80, 0x90, 0x05, 0x33], 3),
+            0x15
+        );
+        assert_eq!(Checksum::WrappingAdd.calculate(&[0x33], 0), 0);
+    }
+
+    #[test]
+    fn crc8_matches_the_check_value() {
+        //the standard check value of crc8 with polynomial 0x07 and initial value 0
+        assert_eq!(Checksum::Crc8.calculate(b"123456789", 9), 0xF4);
+    }
+
+    #[test]
+    fn the_checksum_of_a_frame_is_detected() {
+        let mut frame = b"123456789?".to_vec();
+        frame[9] = 0xF4;
+        assert_eq!(Checksum::detect(&frame, 9), Some(Checksum::Crc8));
+        frame[9] = Checksum::WrappingAdd.calculate(&frame, 9);
+        assert_eq!(Checksum::detect(&frame, 9), Some(Checksum::WrappingAdd));
+        frame[9] ^= 0xFF;
+        assert_eq!(Checksum::detect(&frame, 9), None);
+    }
+
+    #[test]
+    fn the_srec_header_names_its_firmware() {
+        let header = srec_record('0', &[0, 0], b"20-10-1-5-0-0-9.srec");
+        let lines = [header.as_str()];
+        assert_eq!(srec_header(&lines).as_deref(), Some("20-10-1-5-0-0-9.srec"));
+        assert_eq!(
+            srec_header(&lines)
+                .as_deref()
+                .and_then(header_firmware_version),
+            FirmwareVersion::from_filename("20-10-1-5-0-0-9.srec".to_string())
+        );
+        assert_eq!(byte_swapped_header_version(&lines), None);
+    }
+
+    #[test]
+    fn a_byte_swapped_srec_header_is_recognized() {
+        let swapped: Vec<u8> = b"20-10-1-5-0-0-9.srec"
+            .chunks(2)
+            .flat_map(|pair| pair.iter().rev().copied())
+            .collect();
+        let header = srec_record('0', &[0, 0], &swapped);
+        let lines = [header.as_str()];
+        assert_eq!(
+            srec_header(&lines)
+                .as_deref()
+                .and_then(header_firmware_version),
+            None
+        );
+        assert_eq!(
+            byte_swapped_header_version(&lines),
+            FirmwareVersion::from_filename("20-10-1-5-0-0-9.srec".to_string())
+        );
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        assert_eq!(
+            json_string(r#"slot "2" at C:\dev"#),
+            r#""slot \"2\" at C:\\dev""#
+        );
+        assert_eq!(
+            json_string("could not read\nline 2\r\tend\u{1}"),
+            r#""could not read\nline 2\r\tend\u0001""#
+        );
+    }
+
+    fn parse_options(args: &[&str]) -> Result<Options, String> {
+        Options::parse(["go-modules"].iter().chain(args).map(|arg| arg.to_string()))
+            .map(|(options, _)| options)
+    }
+
+    #[test]
+    fn the_spi_speed_can_be_set() {
+        assert_eq!(
+            parse_options(&[]).map(|options| options.spi_speed),
+            Ok(None)
+        );
+        assert_eq!(
+            parse_options(&["--spi-speed", "4000000"]).map(|options| options.spi_speed),
+            Ok(Some(4_000_000))
+        );
+        assert!(parse_options(&["--spi-speed", "0"]).is_err());
+        assert!(parse_options(&["--spi-speed", "fast"]).is_err());
+    }
+
+    #[test]
+    fn a_module_type_matches_its_short_and_full_name() {
+        let output = FirmwareVersion {
+            firmware: [20, 20, 1, 5, 0, 0, 3],
+        };
+        assert!(output.is_module_type("2chOut"));
+        assert!(output.is_module_type("2-ch-out"));
+        assert!(output.is_module_type("2 Channel Output"));
+        assert!(!output.is_module_type("6chIn"));
+        assert!(!FirmwareVersion {
+            firmware: [20, 99, 99, 5, 0, 0, 3]
+        }
+        .is_module_type("2chOut"));
+    }
+
+    #[test]
+    fn a_frame_still_ending_in_the_prefill_is_refused() {
+        //the header and payload sum to 0xFF, so the prefilled checksum byte passes the wrapping add
+        let mut frame = [0u8; BOOTMESSAGE_LENGTH + 1];
+        frame[0] = 0xFF;
+        frame[BOOTMESSAGE_LENGTH - 1] = 0xFF;
+        frame[BOOTMESSAGE_LENGTH] = 0xFF;
+        assert!(BootResponse::parse(&frame, Checksum::WrappingAdd).is_none());
+        //the byte after the checksum was clocked in, so the frame is complete
+        frame[BOOTMESSAGE_LENGTH] = 0;
+        assert!(BootResponse::parse(&frame, Checksum::WrappingAdd).is_some());
+    }
+}
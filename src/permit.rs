@@ -0,0 +1,27 @@
+//! Opting in to destructive module operations
+//!
+//! Every operation that can send the type 29 wipe, overwriting a module or wiping it after a failed upload, takes a
+//! [`WipePermit`]. The only ways to get one are [`WipePermit::for_update`] and [`WipePermit::for_overwrite`], called
+//! in the command dispatch by the two commands that flash, the restore of wiped modules runs within update. Scan,
+//! sync, diff and benchmark never get one, so a read-only path that ends up calling a flashing function doesn't
+//! compile instead of wiping a module. A permit can't be copied or cloned, it is handed down by value and only
+//! borrowed by the module operations themselves.
+
+/// proof that the caller chose to change the firmware of a module, can't be built outside this module
+pub struct WipePermit(());
+
+impl WipePermit {
+    fn acquire() -> Self {
+        Self(())
+    }
+
+    /// the permit of the update command, which also covers restoring the modules an interrupted upload left wiped
+    pub(crate) fn for_update() -> Self {
+        Self::acquire()
+    }
+
+    /// the permit of the overwrite command
+    pub(crate) fn for_overwrite() -> Self {
+        Self::acquire()
+    }
+}
@@ -0,0 +1,177 @@
+//! Flash controllers remotely over ssh
+//!
+//! The host running `go-modules --remote <host> <command>` starts `go-modules --agent <command>` on every remote
+//! controller over ssh, the agent runs the command like it would locally but reports its progress over stdout with
+//! the line based protocol below instead of drawing progress bars.
+//!
+//! Wire protocol version 1, every protocol line starts with `@`, all other lines are regular output of the agent:
+//! ``` text
+//! @agent <version>                    first line sent by the agent, the protocol version it speaks
+//! @progress <slot> <position> <length> upload progress of the module in <slot>, sent on every uploaded firmware line
+//! ```
+//! The exit code of the remote go-modules is passed back by ssh and decides if that controller succeeded.
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    process::{exit, Command, Stdio},
+    thread,
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+use crate::{print_error, print_line};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// announce the protocol version to the host driving this agent
+pub fn announce_agent() {
+    println!("@agent {}", PROTOCOL_VERSION);
+}
+
+/// report upload progress of a slot to the host driving this agent
+pub fn report_progress(slot: u8, progress: &ProgressBar) {
+    println!(
+        "@progress {} {} {}",
+        slot,
+        progress.position(),
+        progress.length().unwrap_or(0)
+    );
+}
+
+/// run the command line on all hosts at once and exit with an error if any of them failed
+pub fn run(hosts: &[String], style: ProgressStyle) -> ! {
+    let args = forwarded_args();
+    if args.is_empty() {
+        print_error("--remote requires a command, the tui is not available remotely");
+        exit(-1);
+    }
+    let multi_progress = MultiProgress::new();
+    let handles: Vec<(String, thread::JoinHandle<bool>)> = hosts
+        .iter()
+        .map(|host| {
+            let (host_thread, args, multi_progress, style) = (
+                host.clone(),
+                args.clone(),
+                multi_progress.clone(),
+                style.clone(),
+            );
+            (
+                host.clone(),
+                thread::spawn(move || run_host(&host_thread, &args, &multi_progress, &style)),
+            )
+        })
+        .collect();
+
+    let mut failed = Vec::new();
+    for (host, handle) in handles {
+        if !handle.join().unwrap_or(false) {
+            failed.push(host);
+        }
+    }
+    if failed.is_empty() {
+        println!("All {} controllers succeeded", hosts.len());
+        exit(0);
+    }
+    print_error(format!("Failed on: {}", failed.join(", ")));
+    exit(-1);
+}
+
+/// run the command line on one host, returns whether the remote go-modules succeeded
+fn run_host(
+    host: &str,
+    args: &[String],
+    multi_progress: &MultiProgress,
+    style: &ProgressStyle,
+) -> bool {
+    let remote_command = format!(
+        "go-modules --agent {} 2>&1",
+        args.iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<String>>()
+            .join(" ")
+    );
+    let mut child = match Command::new("ssh")
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .arg(host)
+        .arg(remote_command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            print_line(
+                multi_progress,
+                format!("[{}] could not start ssh: {}", host, err),
+            );
+            return false;
+        }
+    };
+
+    let mut bars: HashMap<u8, ProgressBar> = HashMap::new();
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            let fields: Vec<&str> = line.split(' ').collect();
+            match fields.as_slice() {
+                ["@agent", version] => {
+                    if version.parse::<u32>().ok() != Some(PROTOCOL_VERSION) {
+                        print_line(
+                            multi_progress,
+                            format!(
+                                "[{}] Warning: agent speaks protocol version {}, expected {}",
+                                host, version, PROTOCOL_VERSION
+                            ),
+                        );
+                    }
+                }
+                ["@progress", slot, position, length] => {
+                    if let (Ok(slot), Ok(position), Ok(length)) = (
+                        slot.parse::<u8>(),
+                        position.parse::<u64>(),
+                        length.parse::<u64>(),
+                    ) {
+                        let bar = bars.entry(slot).or_insert_with(|| {
+                            let bar = multi_progress.add(ProgressBar::new(length));
+                            bar.set_style(style.clone());
+                            bar.set_message(format!("{} slot {}", host, slot));
+                            bar
+                        });
+                        bar.set_length(length);
+                        bar.set_position(position);
+                    }
+                }
+                _ => print_line(multi_progress, format!("[{}] {}", host, line)),
+            }
+        }
+    }
+    let succeeded = child.wait().map(|status| status.success()).unwrap_or(false);
+    for bar in bars.values() {
+        if succeeded {
+            bar.finish();
+        } else {
+            bar.abandon();
+        }
+    }
+    succeeded
+}
+
+/// the command line of this invocation without the --remote options, to run on the remote controllers
+fn forwarded_args() -> Vec<String> {
+    let mut args = std::env::args().skip(1);
+    let mut forwarded = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--remote" {
+            args.next();
+        } else {
+            forwarded.push(arg);
+        }
+    }
+    forwarded
+}
+
+/// quote an argument for the remote shell
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
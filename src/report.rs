@@ -0,0 +1,51 @@
+//! Report the module inventory to a provisioning server
+//!
+//! With the `report` feature every successful run POSTs the modules file as json to the url given with
+//! `--report-url`, without the feature `--report-url` is refused at startup. A report that can't be delivered only
+//! warns, whatever was flashed stays flashed.
+
+#[cfg(feature = "report")]
+use std::time::Duration;
+
+/// how many times a report is sent before giving up
+#[cfg(feature = "report")]
+const ATTEMPTS: u32 = 3;
+/// how long a single attempt may take
+#[cfg(feature = "report")]
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// whether this build can send reports
+pub const SUPPORTED: bool = cfg!(feature = "report");
+
+/// POST the json body to the url, warning when it couldn't be delivered after all attempts
+#[cfg(feature = "report")]
+pub fn post(url: &str, body: &str) {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    for attempt in 1..=ATTEMPTS {
+        match agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(body)
+        {
+            Ok(_) => return,
+            Err(err) => {
+                crate::print_warning(format!(
+                    "Warning: could not report to {} (attempt {} of {}): {}",
+                    url, attempt, ATTEMPTS, err
+                ));
+                if attempt < ATTEMPTS {
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+            }
+        }
+    }
+}
+
+/// never reached, --report-url is refused when SUPPORTED is false
+#[cfg(not(feature = "report"))]
+pub fn post(url: &str, _body: &str) {
+    crate::print_warning(format!(
+        "Warning: not reporting to {}, built without the report feature",
+        url
+    ));
+}
@@ -0,0 +1,592 @@
+//! Access to the module spi buses and interrupt lines
+//!
+//! With the `hardware` feature (default) this uses spidev and the gpio character devices, without it the stub below
+//! is used which never finds a module, so the tool can be built and checked on machines without Linux spi/gpio.
+//! The `pretend` feature replaces both with simulated modules that answer like a bootloader does, so scan, update and
+//! overwrite can be tried out on a laptop, it takes precedence over the `hardware` feature.
+//! The unit tests always use the mock transport instead, whose spidevs answer the way a test scripts them, so the
+//! upload loop can be tested with any set of features.
+
+use std::{
+    env, io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+#[cfg(all(feature = "hardware", not(feature = "pretend"), not(test)))]
+pub use hardware::{Interrupt, Spi};
+#[cfg(test)]
+pub use mock::{Interrupt, Spi};
+#[cfg(all(feature = "pretend", not(test)))]
+pub use pretend::{Interrupt, Spi};
+#[cfg(not(any(feature = "hardware", feature = "pretend", test)))]
+pub use stub::{Interrupt, Spi};
+
+/// whether the modules are simulated, the controller checks that need real hardware are skipped then
+pub const PRETEND: bool = cfg!(all(feature = "pretend", not(test)));
+
+/// the device tree hardware string of the controller the pretend feature simulates, GO_MODULES_PRETEND_HARDWARE or
+/// a Moduline IV
+pub fn pretend_hardware() -> String {
+    env::var("GO_MODULES_PRETEND_HARDWARE")
+        .unwrap_or_else(|_| "GOcontroll Moduline IV V3.06".to_string())
+}
+
+/// log every frame on the spi buses to stderr, set once at startup by --trace-spi
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+/// print a frame as hex with the bootloader message type and, for firmware lines, the line number it carries
+#[cfg_attr(
+    not(any(feature = "hardware", feature = "pretend", test)),
+    allow(dead_code)
+)]
+fn trace(path: &str, direction: &str, frame: &[u8]) {
+    if !TRACE.load(Ordering::Relaxed) {
+        return;
+    }
+    let kind = match frame.first() {
+        Some(9) => "info".to_string(),
+        Some(19) => "exit".to_string(),
+        Some(29) => "wipe".to_string(),
+        Some(39) => match frame.get(6..8) {
+            Some(line) => format!("line {}", u16::from_be_bytes([line[0], line[1]])),
+            None => "line".to_string(),
+        },
+        Some(49) => "status".to_string(),
+        Some(other) => format!("type {}", other),
+        None => "empty".to_string(),
+    };
+    let hex: Vec<String> = frame.iter().map(|byte| format!("{:02x}", byte)).collect();
+    eprintln!("spi {} {} {}: {}", path, direction, kind, hex.join(" "));
+}
+
+/// whether an spi error means the device itself went away, like a driver reload, instead of a failed transfer
+pub fn device_gone(err: &io::Error) -> bool {
+    use nix::errno::Errno;
+    err.kind() == io::ErrorKind::NotFound
+        || [Errno::ENODEV, Errno::ENXIO, Errno::EBADF, Errno::ESHUTDOWN]
+            .iter()
+            .any(|errno| err.raw_os_error() == Some(*errno as i32))
+}
+
+#[cfg(all(feature = "hardware", not(feature = "pretend"), not(test)))]
+mod hardware {
+    use std::{collections::BTreeMap, fs::File, io, sync::Mutex};
+
+    use futures::{stream::Next, StreamExt};
+    use gpio_cdev::{AsyncLineEventHandle, Chip, EventRequestFlags, LineRequestFlags};
+    use spidev::{SpiModeFlags, Spidev, SpidevOptions, SpidevTransfer};
+
+    use crate::print_warning;
+
+    pub struct Spi {
+        spidev: Spidev,
+        path: String,
+        /// the speed of the last configure, applied again when the device is reopened
+        max_speed_hz: Option<u32>,
+    }
+
+    impl Spi {
+        pub fn open(path: &str) -> io::Result<Self> {
+            Ok(Self {
+                spidev: Spidev::new(File::open(path)?),
+                path: path.to_string(),
+                max_speed_hz: None,
+            })
+        }
+
+        pub fn path(&self) -> &str {
+            &self.path
+        }
+
+        /// open the device node again with the same configuration, for when it disappeared and came back
+        pub fn reopen(&mut self) -> io::Result<()> {
+            self.spidev = Spidev::new(File::open(&self.path)?);
+            match self.max_speed_hz {
+                Some(max_speed_hz) => self.configure(max_speed_hz),
+                None => Ok(()),
+            }
+        }
+
+        pub fn configure(&mut self, max_speed_hz: u32) -> io::Result<()> {
+            self.spidev.configure(
+                &SpidevOptions::new()
+                    .bits_per_word(8)
+                    .max_speed_hz(max_speed_hz)
+                    .mode(SpiModeFlags::SPI_MODE_0)
+                    .build(),
+            )?;
+            self.max_speed_hz = Some(max_speed_hz);
+            Ok(())
+        }
+
+        pub fn write(&mut self, tx_buf: &[u8]) -> io::Result<()> {
+            super::trace(&self.path, "tx", tx_buf);
+            retry_interrupted(|| self.spidev.transfer(&mut SpidevTransfer::write(tx_buf)))
+        }
+
+        /// full duplex transfer, the buffers have to be the same length \
+        /// rx_buf is filled with 0xFF first, so an incomplete transfer can't leave the previous frame behind to be
+        /// mistaken for a valid response. The length the driver transferred isn't checked, a frame that is only partly
+        /// filled can still pass its checksum by chance, BootResponse refuses the ones whose end is still all 0xFF
+        pub fn transfer(&mut self, tx_buf: &[u8], rx_buf: &mut [u8]) -> io::Result<()> {
+            if tx_buf.len() != rx_buf.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "spi transfer of {} bytes into a {} byte buffer",
+                        tx_buf.len(),
+                        rx_buf.len()
+                    ),
+                ));
+            }
+            super::trace(&self.path, "tx", tx_buf);
+            retry_interrupted(|| {
+                rx_buf.fill(0xFF);
+                self.spidev
+                    .transfer(&mut SpidevTransfer::read_write(tx_buf, rx_buf))
+            })?;
+            //the response frame is classified by its own first byte, the command it answers
+            super::trace(&self.path, "rx", rx_buf);
+            Ok(())
+        }
+    }
+
+    /// repeat an ioctl that was interrupted by a signal (EINTR), so a signal arriving during a flash doesn't count as a
+    /// failed line, the interrupted ioctl didn't get the frame onto the bus so sending the same frame again is safe
+    fn retry_interrupted(mut ioctl: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+        loop {
+            match ioctl() {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// the gpiochips opened so far by path, shared by the slots scanned in parallel so several slots on one chip
+    /// don't race each other opening it, every slot still requests and owns only its own line
+    static CHIPS: Mutex<BTreeMap<String, Chip>> = Mutex::new(BTreeMap::new());
+
+    pub struct Interrupt {
+        handle: AsyncLineEventHandle,
+    }
+
+    impl Interrupt {
+        /// request the falling edge events of a module interrupt line
+        pub fn request(chip: &str, line: u32, slot: u8) -> Option<Self> {
+            let mut chips = CHIPS
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !chips.contains_key(chip) {
+                let opened = Chip::new(chip)
+                    .map_err(|_| print_warning(format!("Could not get slot {slot} interrupt chip")))
+                    .ok()?;
+                chips.insert(chip.to_string(), opened);
+            }
+            let line = chips
+                .get_mut(chip)?
+                .get_line(line)
+                .map_err(|_| print_warning(format!("Could not get slot {slot} interrupt line")))
+                .ok()?;
+            let handle = line
+                .async_events(
+                    LineRequestFlags::INPUT,
+                    EventRequestFlags::FALLING_EDGE,
+                    format!("module {slot} interrupt").as_str(),
+                )
+                .map_err(|err| {
+                    print_warning(format!(
+                        "Could not get slot {slot} interrupt line handle: {err}"
+                    ))
+                })
+                .ok()?;
+            Some(Self { handle })
+        }
+
+        /// a future that resolves on the next interrupt
+        pub fn next(&mut self) -> Next<'_, AsyncLineEventHandle> {
+            self.handle.next()
+        }
+    }
+}
+
+#[cfg(not(any(feature = "hardware", feature = "pretend", test)))]
+mod stub {
+    use std::{future::Pending, io};
+
+    /// never opens, so no module is ever found
+    pub struct Spi;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "built without the hardware feature",
+        )
+    }
+
+    impl Spi {
+        pub fn open(_path: &str) -> io::Result<Self> {
+            Err(unsupported())
+        }
+
+        pub fn path(&self) -> &str {
+            ""
+        }
+
+        pub fn reopen(&mut self) -> io::Result<()> {
+            Err(unsupported())
+        }
+
+        pub fn configure(&mut self, _max_speed_hz: u32) -> io::Result<()> {
+            Err(unsupported())
+        }
+
+        pub fn write(&mut self, _tx_buf: &[u8]) -> io::Result<()> {
+            Err(unsupported())
+        }
+
+        pub fn transfer(&mut self, _tx_buf: &[u8], _rx_buf: &mut [u8]) -> io::Result<()> {
+            Err(unsupported())
+        }
+    }
+
+    /// never requested, modules fall back to timed mode
+    pub struct Interrupt;
+
+    impl Interrupt {
+        pub fn request(_chip: &str, _line: u32, _slot: u8) -> Option<Self> {
+            None
+        }
+
+        pub fn next(&mut self) -> Pending<()> {
+            std::future::pending()
+        }
+    }
+}
+
+/// a module in its bootloader as far as this tool talks to it, for the pretend feature and the tests
+#[cfg(any(feature = "pretend", test))]
+pub mod simulated {
+    use crate::{Checksum, BOOTMESSAGE_LENGTH};
+
+    pub struct Bootloader {
+        pub firmware: [u8; 7],
+        manufacturer: u32,
+        qr_front: u32,
+        qr_back: u32,
+        /// the frame clocked out on the next transfer, the answer to the message before it like a real module
+        response: [u8; BOOTMESSAGE_LENGTH],
+        /// the firmware line messages received, a line sent again after an error counts again
+        pub lines_received: usize,
+    }
+
+    impl Bootloader {
+        pub fn new(firmware: [u8; 7], manufacturer: u32, qr_front: u32, qr_back: u32) -> Self {
+            Self {
+                firmware,
+                manufacturer,
+                qr_front,
+                qr_back,
+                response: [0xFF; BOOTMESSAGE_LENGTH],
+                lines_received: 0,
+            }
+        }
+
+        /// a frame of the message type with the payload from byte 6 on and the wrapping add checksum
+        fn frame(message_type: u8, payload: &[u8]) -> [u8; BOOTMESSAGE_LENGTH] {
+            let mut frame = [0u8; BOOTMESSAGE_LENGTH];
+            frame[0] = message_type;
+            frame[1] = (BOOTMESSAGE_LENGTH - 1) as u8;
+            frame[2] = message_type;
+            frame[6..6 + payload.len()].copy_from_slice(payload);
+            frame[BOOTMESSAGE_LENGTH - 1] =
+                Checksum::WrappingAdd.calculate(&frame, BOOTMESSAGE_LENGTH - 1);
+            frame
+        }
+
+        fn info(&self) -> [u8; BOOTMESSAGE_LENGTH] {
+            let mut payload = self.firmware.to_vec();
+            payload.extend(self.manufacturer.to_be_bytes());
+            payload.extend(self.qr_front.to_be_bytes());
+            payload.extend(self.qr_back.to_be_bytes());
+            Self::frame(9, &payload)
+        }
+
+        /// take in a written message, what it answers is clocked out on the next transfer
+        pub fn receive(&mut self, tx_buf: &[u8]) {
+            match tx_buf.first() {
+                //the dummy message is sent right before the reset, after which the bootloader has its info ready
+                _ if tx_buf.len() == crate::DUMMY_MESSAGE.len() => self.response = self.info(),
+                //the wipe stores the software version of the firmware that follows
+                Some(29) if tx_buf.len() > 8 => self.firmware[4..7].copy_from_slice(&tx_buf[6..9]),
+                Some(39) if tx_buf.len() > 8 => {
+                    self.lines_received += 1;
+                    self.response = if tx_buf[8] == 7 {
+                        //the last line starts the firmware, which answers the next status request with 20
+                        Self::frame(49, &[20])
+                    } else {
+                        //the line number followed by the status, 1 is received correctly
+                        Self::frame(39, &[tx_buf[6], tx_buf[7], 1])
+                    }
+                }
+                //a status request repeats the answer for the last line
+                _ => (),
+            }
+        }
+
+        /// full duplex, clock out the answer to the previous message while this one comes in
+        pub fn transfer(&mut self, tx_buf: &[u8], rx_buf: &mut [u8]) {
+            rx_buf.fill(0);
+            let length = rx_buf.len().min(BOOTMESSAGE_LENGTH);
+            rx_buf[..length].copy_from_slice(&self.response[..length]);
+            self.receive(tx_buf);
+        }
+    }
+}
+
+#[cfg(all(feature = "pretend", not(test)))]
+mod pretend {
+    use std::{collections::BTreeMap, future::Pending, io, sync::Mutex};
+
+    use super::simulated::Bootloader;
+
+    /// the simulated modules by spidev: firmware, manufacturer, front and back QR code \
+    /// a spidev that isn't listed has an empty slot, the Moduline IV slot on spidev2.2 is left empty that way
+    const MODULES: &[(&str, [u8; 7], u32, u32, u32)] = &[
+        (
+            "/dev/spidev1.0",
+            [20, 10, 1, 5, 0, 0, 9],
+            20,
+            100001,
+            200001,
+        ),
+        (
+            "/dev/spidev1.1",
+            [20, 20, 2, 5, 0, 1, 2],
+            20,
+            100002,
+            200002,
+        ),
+        (
+            "/dev/spidev2.0",
+            [20, 10, 3, 1, 0, 2, 0],
+            20,
+            100003,
+            200003,
+        ),
+        (
+            "/dev/spidev2.1",
+            [20, 20, 3, 1, 0, 1, 0],
+            20,
+            100004,
+            200004,
+        ),
+        (
+            "/dev/spidev2.3",
+            [20, 10, 2, 3, 0, 1, 4],
+            20,
+            100005,
+            200005,
+        ),
+        //wiped by an interrupted upload, so the restore of a wiped module can be tried too
+        (
+            "/dev/spidev0.0",
+            [20, 10, 1, 5, 255, 255, 255],
+            20,
+            100006,
+            200006,
+        ),
+        (
+            "/dev/spidev0.1",
+            [20, 20, 1, 2, 0, 0, 7],
+            20,
+            100007,
+            200007,
+        ),
+    ];
+
+    /// kept for the whole run so a flash shows up in the scans after it
+    static MODULES_BY_SPIDEV: Mutex<BTreeMap<String, Bootloader>> = Mutex::new(BTreeMap::new());
+
+    pub struct Spi {
+        path: String,
+    }
+
+    impl Spi {
+        pub fn open(path: &str) -> io::Result<Self> {
+            let mut modules = MODULES_BY_SPIDEV
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !modules.contains_key(path) {
+                if let Some((_, firmware, manufacturer, qr_front, qr_back)) =
+                    MODULES.iter().find(|module| module.0 == path)
+                {
+                    modules.insert(
+                        path.to_string(),
+                        Bootloader::new(*firmware, *manufacturer, *qr_front, *qr_back),
+                    );
+                }
+            }
+            Ok(Self {
+                path: path.to_string(),
+            })
+        }
+
+        pub fn path(&self) -> &str {
+            &self.path
+        }
+
+        pub fn reopen(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn configure(&mut self, _max_speed_hz: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn write(&mut self, tx_buf: &[u8]) -> io::Result<()> {
+            super::trace(&self.path, "tx", tx_buf);
+            if let Some(module) = MODULES_BY_SPIDEV
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get_mut(&self.path)
+            {
+                module.receive(tx_buf);
+            }
+            Ok(())
+        }
+
+        /// an empty slot reads all 0xFF like a bus nothing drives
+        pub fn transfer(&mut self, tx_buf: &[u8], rx_buf: &mut [u8]) -> io::Result<()> {
+            super::trace(&self.path, "tx", tx_buf);
+            rx_buf.fill(0xFF);
+            if let Some(module) = MODULES_BY_SPIDEV
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get_mut(&self.path)
+            {
+                module.transfer(tx_buf, rx_buf);
+            }
+            super::trace(&self.path, "rx", rx_buf);
+            Ok(())
+        }
+    }
+
+    /// never requested, the simulated modules are driven in timed mode
+    pub struct Interrupt;
+
+    impl Interrupt {
+        pub fn request(_chip: &str, _line: u32, _slot: u8) -> Option<Self> {
+            None
+        }
+
+        pub fn next(&mut self) -> Pending<()> {
+            std::future::pending()
+        }
+    }
+}
+
+/// spidevs that answer the way a test scripts them, see script
+#[cfg(test)]
+pub mod mock {
+    use std::{collections::BTreeMap, future::Pending, io, sync::Mutex};
+
+    /// what the module on a mock spidev does with a frame, rx_buf is empty for a write and 0xFF filled for a transfer
+    type Responder = Box<dyn FnMut(&[u8], &mut [u8]) -> io::Result<()> + Send>;
+
+    static RESPONDERS: Mutex<BTreeMap<String, Responder>> = Mutex::new(BTreeMap::new());
+
+    /// answer every frame on the spidev at path with responder, the tests run at once so each uses its own path
+    pub fn script(
+        path: &str,
+        responder: impl FnMut(&[u8], &mut [u8]) -> io::Result<()> + Send + 'static,
+    ) {
+        RESPONDERS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(path.to_string(), Box::new(responder));
+    }
+
+    fn respond(path: &str, tx_buf: &[u8], rx_buf: &mut [u8]) -> io::Result<()> {
+        let mut responders = RESPONDERS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match responders.get_mut(path) {
+            Some(responder) => responder(tx_buf, rx_buf),
+            None => Err(io::Error::from(io::ErrorKind::NotFound)),
+        }
+    }
+
+    /// a spidev that was never scripted doesn't exist
+    pub struct Spi {
+        path: String,
+    }
+
+    impl Spi {
+        pub fn open(path: &str) -> io::Result<Self> {
+            if !RESPONDERS
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .contains_key(path)
+            {
+                return Err(io::Error::from(io::ErrorKind::NotFound));
+            }
+            Ok(Self {
+                path: path.to_string(),
+            })
+        }
+
+        pub fn path(&self) -> &str {
+            &self.path
+        }
+
+        pub fn reopen(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn configure(&mut self, _max_speed_hz: u32) -> io::Result<()> {
+            Ok(())
+        }
+
+        pub fn write(&mut self, tx_buf: &[u8]) -> io::Result<()> {
+            super::trace(&self.path, "tx", tx_buf);
+            respond(&self.path, tx_buf, &mut [])
+        }
+
+        /// checks the buffers and prefills rx_buf with 0xFF like the hardware transfer, so a responder that fills
+        /// only part of it acts like a short read
+        pub fn transfer(&mut self, tx_buf: &[u8], rx_buf: &mut [u8]) -> io::Result<()> {
+            if tx_buf.len() != rx_buf.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "spi transfer of {} bytes into a {} byte buffer",
+                        tx_buf.len(),
+                        rx_buf.len()
+                    ),
+                ));
+            }
+            super::trace(&self.path, "tx", tx_buf);
+            rx_buf.fill(0xFF);
+            respond(&self.path, tx_buf, rx_buf)?;
+            super::trace(&self.path, "rx", rx_buf);
+            Ok(())
+        }
+    }
+
+    /// never requested, the mock modules are driven in timed mode
+    pub struct Interrupt;
+
+    impl Interrupt {
+        pub fn request(_chip: &str, _line: u32, _slot: u8) -> Option<Self> {
+            None
+        }
+
+        pub fn next(&mut self) -> Pending<()> {
+            std::future::pending()
+        }
+    }
+}
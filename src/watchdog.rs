@@ -0,0 +1,53 @@
+//! Keeping a hardware watchdog fed while modules are flashed
+//!
+//! With nodered and go-simulink stopped nothing may be feeding the watchdog, and a controller that resets halfway
+//! through a flash leaves the module without firmware. `--watchdog-path <dev>` opens the watchdog device once the
+//! services are stopped and writes to it every `--watchdog-interval` from a thread of its own, so a busy upload never
+//! delays it. Opening the device starts the watchdog if it wasn't running yet, so every exit of the tool writes the
+//! magic 'V' before closing it, which stops the watchdog again unless the kernel was built with nowayout. The service
+//! that normally feeds it starts it again when it opens the device after the restart.
+
+use std::{fs::File, io, io::Write, sync::Mutex, thread, time::Duration};
+
+/// the opened watchdog device, None when --watchdog-path isn't given or after stop
+static WATCHDOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// open the watchdog at path and feed it every interval until stop is called
+pub fn start(path: &str, interval: Duration) -> io::Result<()> {
+    let file = File::options().write(true).open(path)?;
+    *WATCHDOG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(file);
+    let path = path.to_string();
+    thread::spawn(move || loop {
+        {
+            let mut watchdog = WATCHDOG
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let Some(file) = watchdog.as_mut() else {
+                return;
+            };
+            //any character but 'V' only feeds it
+            if let Err(err) = file.write_all(b"\0").and_then(|()| file.flush()) {
+                crate::print_warning(format!(
+                    "Warning: could not feed the watchdog {}: {}",
+                    path, err
+                ));
+                return;
+            }
+        }
+        thread::sleep(interval);
+    });
+    Ok(())
+}
+
+/// close the watchdog with the magic 'V' so it doesn't reset the controller after the tool exits
+pub fn stop() {
+    let watchdog = WATCHDOG
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .take();
+    if let Some(mut file) = watchdog {
+        _ = file.write_all(b"V").and_then(|()| file.flush());
+    }
+}